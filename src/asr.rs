@@ -1,104 +1,489 @@
-#[cfg(feature = "realtime")]
+#[cfg(feature = "asr")]
 use anyhow::{anyhow, Result};
-#[cfg(feature = "realtime")]
+#[cfg(feature = "asr")]
 use hound::WavReader;
-#[cfg(feature = "realtime")]
-use reqwest;
-#[cfg(feature = "realtime")]
+#[cfg(feature = "asr")]
+use lazy_static::lazy_static;
+#[cfg(feature = "asr")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "asr")]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "asr")]
+use std::time::Instant;
 
+#[cfg(feature = "asr")]
+#[derive(Clone)]
 pub struct AsrConfig {
     pub api_key: Option<String>,
     pub use_local: bool,  // If true, use local API, otherwise use OpenAI
+    pub local_url: Option<String>,  // Explicit local endpoint, otherwise probe the defaults
+    pub connect_timeout: Duration,
+    pub timeout: Duration,  // Whole request, upload through response
+    pub retry: RetryPolicy,
+    pub failover_local: bool,  // Fall back to the local API while OpenAI is failing
+    pub max_upload_bytes: usize,  // Larger WAV files are split before upload
+    pub chunk_concurrency: usize,
 }
 
-#[cfg(feature = "realtime")]
+/// Backoff for transient OpenAI failures (429, 5xx, connection errors).
+#[cfg_attr(not(feature = "asr"), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+#[cfg(feature = "asr")]
+impl RetryPolicy {
+    /// Exponential backoff for the given zero-based retry, jittered into [50%, 100%).
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self.base_delay.saturating_mul(1 << retry.min(16)).min(self.max_delay);
+        ceiling.mul_f64(0.5 + jitter() / 2.0)
+    }
+}
+
+// Uniform in [0, 1). RandomState is freshly keyed per instance, which is random enough here.
+#[cfg(feature = "asr")]
+fn jitter() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    let bits = std::collections::hash_map::RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Why a transcription could not be produced.
+#[cfg(feature = "asr")]
+#[derive(Debug, thiserror::Error)]
+pub enum AsrError {
+    #[error("OpenAI API key required. Set OPENAI_API_KEY environment variable or pass --api-key")]
+    MissingApiKey,
+    #[error("ASR backend rejected the credentials: {0}")]
+    Unauthorized(String),
+    #[error("ASR backend unreachable: {0}")]
+    Unreachable(String),
+    #[error("Invalid audio: {0}")]
+    AudioFormat(String),
+    #[error("Audio rejected as too large: {0}")]
+    TooLarge(String),
+    #[error("No speech detected in audio")]
+    NoSpeech,
+    #[error("ASR backend error: {0}")]
+    Backend(String),
+    #[error("ASR request timed out after {}s", .0.as_secs())]
+    Timeout(Duration),
+    #[error("Transcription cancelled")]
+    Cancelled,
+}
+
+/// Encoded audio handed to the Whisper backends as-is.
+#[cfg(feature = "asr")]
+pub struct AudioInput {
+    pub bytes: Vec<u8>,
+    pub file_name: String,
+    pub mime: String,
+}
+
+#[cfg(feature = "asr")]
+impl AudioInput {
+    /// Wrap a WAV file's bytes after checking that the header parses.
+    pub fn wav(bytes: Vec<u8>) -> Result<Self, AsrError> {
+        let spec = WavReader::new(std::io::Cursor::new(&bytes))
+            .map_err(|e| AsrError::AudioFormat(format!("not a valid WAV file: {}", e)))?
+            .spec();
+        log::info!("Processing WAV audio: {} channels, {} Hz", spec.channels, spec.sample_rate);
+        Ok(Self { bytes, file_name: "audio.wav".to_string(), mime: "audio/wav".to_string() })
+    }
+
+    /// Only WAV can be split when it exceeds the upload limit; other formats go as they are.
+    fn is_wav(&self) -> bool {
+        self.bytes.len() > 12 && &self.bytes[..4] == b"RIFF" && &self.bytes[8..12] == b"WAVE"
+    }
+
+    /// Encode mono 16-bit PCM samples as an in-memory WAV file.
+    #[cfg(feature = "ui")]
+    pub fn from_pcm(samples: &[i16], sample_rate: u32) -> Result<Self, AsrError> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut cursor = std::io::Cursor::new(Vec::with_capacity(44 + samples.len() * 2));
+        {
+            let mut writer = hound::WavWriter::new(&mut cursor, spec)
+                .map_err(|e| AsrError::AudioFormat(format!("failed to create WAV buffer: {}", e)))?;
+            for &sample in samples {
+                writer.write_sample(sample)
+                    .map_err(|e| AsrError::AudioFormat(format!("failed to write sample: {}", e)))?;
+            }
+            writer.finalize()
+                .map_err(|e| AsrError::AudioFormat(format!("failed to finalize WAV buffer: {}", e)))?;
+        }
+        Ok(Self { bytes: cursor.into_inner(), file_name: "audio.wav".to_string(), mime: "audio/wav".to_string() })
+    }
+}
+
+#[cfg(feature = "asr")]
 #[derive(Serialize, Deserialize, Debug)]
 struct WhisperResponse {
     text: String,
+    // Present with OpenAI's `verbose_json` and in many local servers' output
+    #[serde(default)]
+    segments: Vec<Segment>,
 }
 
-#[cfg(feature = "realtime")]
-pub fn transcribe_wav(path: &str, cfg: &AsrConfig) -> Result<String> {
-    // Use async runtime for the API call
-    let rt = tokio::runtime::Runtime::new()?;
-    rt.block_on(transcribe_wav_async(path, cfg))
+/// A stretch of the transcript, timed in seconds from the start of the audio.
+#[cfg(feature = "asr")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
 }
 
-#[cfg(feature = "realtime")]
-async fn transcribe_wav_async(path: &str, cfg: &AsrConfig) -> Result<String> {
-    let reader = WavReader::open(path)?;
-    let spec = reader.spec();
-    
-    log::info!("Processing WAV file: {} channels, {} Hz", spec.channels, spec.sample_rate);
-    
-    // Read the entire file as bytes for upload
-    let file_bytes = std::fs::read(path)
-        .map_err(|e| anyhow!("Failed to read audio file: {}", e))?;
-    
+#[cfg(feature = "asr")]
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    pub text: String,
+    /// Empty when the backend only returns text
+    pub segments: Vec<Segment>,
+}
+
+#[cfg(feature = "asr")]
+impl From<WhisperResponse> for Transcript {
+    fn from(response: WhisperResponse) -> Self {
+        Self { text: response.text, segments: response.segments }
+    }
+}
+
+/// Long-lived ASR handle: one pooled HTTP client shared by every request, so repeated
+/// transcriptions reuse connections. Async callers await it on their own runtime; a
+/// client built with [`AsrClient::with_runtime`] also serves blocking callers.
+#[cfg(feature = "asr")]
+#[derive(Clone)]
+pub struct AsrClient {
+    http: reqwest::Client,
+    cfg: Arc<AsrConfig>,
+    #[cfg(feature = "realtime")]
+    runtime: Option<Arc<tokio::runtime::Runtime>>,
+}
+
+#[cfg(feature = "asr")]
+impl AsrClient {
+    pub fn new(cfg: AsrConfig) -> Result<Self, AsrError> {
+        Ok(Self {
+            http: http_client(&cfg)?,
+            cfg: Arc::new(cfg),
+            #[cfg(feature = "realtime")]
+            runtime: None,
+        })
+    }
+
+    /// Client that owns a runtime for the blocking entry points.
+    #[cfg(feature = "realtime")]
+    pub fn with_runtime(cfg: AsrConfig) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .map_err(|e| anyhow!("Failed to start ASR runtime: {}", e))?;
+        Ok(Self { runtime: Some(Arc::new(runtime)), ..Self::new(cfg)? })
+    }
+
+    /// Whether requests go to a local Whisper server rather than the paid OpenAI API.
+    #[cfg(feature = "ui")]
+    pub fn is_local(&self) -> bool {
+        self.cfg.use_local
+    }
+
+    pub async fn transcribe(&self, audio: &AudioInput) -> Result<String, AsrError> {
+        Ok(self.transcribe_detailed(audio).await?.text)
+    }
+
+    /// Transcribe with segment timings where the backend provides them. WAV files over the
+    /// upload limit are split and transcribed in parallel.
+    pub async fn transcribe_detailed(&self, audio: &AudioInput) -> Result<Transcript, AsrError> {
+        let transcript = if audio.bytes.len() > self.cfg.max_upload_bytes && audio.is_wav() {
+            transcribe_chunked(audio, &self.http, &self.cfg).await?
+        } else {
+            transcribe_once(audio, &self.http, &self.cfg).await?
+        };
+        
+        if transcript.text.trim().is_empty() {
+            return Err(AsrError::NoSpeech);
+        }
+        
+        log::info!("Transcription result: '{}'", transcript.text);
+        Ok(transcript)
+    }
+
+    /// Blocking transcription on the client's runtime, giving up early once `cancel` is set.
+    #[cfg(feature = "realtime")]
+    pub fn transcribe_blocking(&self, audio: &AudioInput, cancel: &crate::cancel::CancelToken) -> Result<String, AsrError> {
+        let runtime = self.runtime.as_ref().expect("blocking ASR calls need AsrClient::with_runtime");
+        runtime.block_on(async {
+            tokio::select! {
+                result = self.transcribe(audio) => result,
+                _ = cancel.cancelled() => Err(AsrError::Cancelled),
+            }
+        })
+    }
+
+    /// Transcribe a WAV file, giving up early once `cancel` is set.
+    #[cfg(feature = "realtime")]
+    pub fn transcribe_wav(&self, path: &str, cancel: &crate::cancel::CancelToken) -> Result<String> {
+        // Read the entire file as bytes for upload
+        let file_bytes = std::fs::read(path)
+            .map_err(|e| anyhow!("Failed to read audio file: {}", e))?;
+        
+        Ok(self.transcribe_blocking(&AudioInput::wav(file_bytes)?, cancel)?)
+    }
+}
+
+#[cfg(feature = "asr")]
+async fn transcribe_once(audio: &AudioInput, http: &reqwest::Client, cfg: &AsrConfig) -> Result<Transcript, AsrError> {
     // Call Whisper API
-    let text = if cfg.use_local {
-        call_local_whisper_api(&file_bytes).await?
+    let started = Instant::now();
+    let (backend, transcript) = if cfg.use_local {
+        ("local", call_local_whisper_api(audio, http, cfg).await)
     } else {
-        call_openai_whisper_api(&file_bytes, &cfg.api_key).await?
+        transcribe_openai(audio, http, cfg).await
     };
-    
-    if text.trim().is_empty() {
-        return Err(anyhow!("No speech detected in audio file"));
+    crate::metrics::observe_asr(backend, started.elapsed().as_secs_f64());
+    transcript
+}
+
+#[cfg(feature = "asr")]
+async fn transcribe_chunked(audio: &AudioInput, http: &reqwest::Client, cfg: &Arc<AsrConfig>) -> Result<Transcript, AsrError> {
+    let chunks = crate::chunking::split_wav(&audio.bytes, cfg.max_upload_bytes)?;
+    let limit = Arc::new(tokio::sync::Semaphore::new(cfg.chunk_concurrency.max(1)));
+    // Dropping the set aborts the remaining chunks, so cancellation reaches them too
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let (http, cfg, limit) = (http.clone(), cfg.clone(), limit.clone());
+        tasks.spawn(async move {
+            let _permit = limit.acquire_owned().await;
+            let transcript = transcribe_once(&chunk.audio, &http, &cfg).await;
+            (index, chunk, transcript)
+        });
     }
     
-    log::info!("Transcription result: '{}'", text);
-    Ok(text)
+    let mut parts = Vec::with_capacity(tasks.len());
+    while let Some(joined) = tasks.join_next().await {
+        let (index, chunk, transcript) = joined
+            .map_err(|e| AsrError::Backend(format!("chunk transcription task failed: {}", e)))?;
+        parts.push((index, chunk, transcript?));
+    }
+    parts.sort_by_key(|(index, _, _)| *index);
+    Ok(crate::chunking::stitch(parts.into_iter().map(|(_, chunk, transcript)| (chunk, transcript)).collect()))
 }
 
-#[cfg(feature = "realtime")]
-async fn call_openai_whisper_api(audio_bytes: &[u8], api_key: &Option<String>) -> Result<String> {
-    let api_key = api_key.as_ref()
-        .ok_or_else(|| anyhow!("OpenAI API key required. Set OPENAI_API_KEY environment variable or pass --api-key"))?;
-    
-    let client = reqwest::Client::new();
+/// Consecutive failed OpenAI calls (each after its retries) that open the circuit.
+#[cfg(feature = "asr")]
+const BREAKER_THRESHOLD: u32 = 3;
+#[cfg(feature = "asr")]
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(60);
+
+#[cfg(feature = "asr")]
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+#[cfg(feature = "asr")]
+impl Breaker {
+    /// Time left while the circuit is open. Once the cooldown has passed requests go
+    /// through again, and a single further failure re-opens it.
+    fn open_for(&mut self) -> Option<Duration> {
+        let until = self.open_until?;
+        let now = Instant::now();
+        if now < until {
+            return Some(until - now);
+        }
+        self.open_until = None;
+        self.failures = BREAKER_THRESHOLD - 1;
+        None
+    }
+
+    fn record(&mut self, ok: bool) {
+        if ok {
+            self.failures = 0;
+            return;
+        }
+        self.failures += 1;
+        if self.failures >= BREAKER_THRESHOLD && self.open_until.is_none() {
+            log::warn!("OpenAI failed {} times in a row; pausing it for {}s", self.failures, BREAKER_COOLDOWN.as_secs());
+            self.open_until = Some(Instant::now() + BREAKER_COOLDOWN);
+        }
+    }
+}
+
+#[cfg(feature = "asr")]
+lazy_static! {
+    static ref OPENAI_BREAKER: Mutex<Breaker> = Mutex::new(Breaker::default());
+}
+
+/// OpenAI behind the circuit breaker, failing over to the local API when configured.
+/// Returns the backend that produced the result for metrics.
+#[cfg(feature = "asr")]
+async fn transcribe_openai(audio: &AudioInput, http: &reqwest::Client, cfg: &AsrConfig) -> (&'static str, Result<Transcript, AsrError>) {
+    let open_for = OPENAI_BREAKER.lock().unwrap().open_for();
+    if let Some(remaining) = open_for {
+        if cfg.failover_local {
+            log::info!("OpenAI circuit open; using local Whisper API");
+            return ("local", call_local_whisper_api(audio, http, cfg).await);
+        }
+        return ("openai", Err(AsrError::Unreachable(format!(
+            "OpenAI failing repeatedly, next attempt in {}s",
+            remaining.as_secs() + 1
+        ))));
+    }
+
+    match call_openai_whisper_api(audio, http, cfg).await {
+        Ok(transcript) => {
+            OPENAI_BREAKER.lock().unwrap().record(true);
+            ("openai", Ok(transcript))
+        }
+        Err(failure) if failure.transient => {
+            OPENAI_BREAKER.lock().unwrap().record(false);
+            if cfg.failover_local {
+                log::warn!("OpenAI transcription failed ({}); failing over to local Whisper API", failure.error);
+                return ("local", call_local_whisper_api(audio, http, cfg).await);
+            }
+            ("openai", Err(failure.error))
+        }
+        Err(failure) => ("openai", Err(failure.error)),
+    }
+}
+
+/// A failed OpenAI request. Transient failures (rate limits, 5xx, network) say nothing
+/// about the request itself and may succeed later; the rest are fatal.
+#[cfg(feature = "asr")]
+struct OpenAiFailure {
+    error: AsrError,
+    transient: bool,
+    retry_after: Option<Duration>,
+}
+
+#[cfg(feature = "asr")]
+impl OpenAiFailure {
+    fn fatal(error: AsrError) -> Self {
+        Self { error, transient: false, retry_after: None }
+    }
+
+    fn transient(error: AsrError, retry_after: Option<Duration>) -> Self {
+        Self { error, transient: true, retry_after }
+    }
+}
+
+#[cfg(feature = "asr")]
+async fn call_openai_whisper_api(audio: &AudioInput, http: &reqwest::Client, cfg: &AsrConfig) -> Result<Transcript, OpenAiFailure> {
+    let api_key = cfg.api_key.as_ref().ok_or(OpenAiFailure::fatal(AsrError::MissingApiKey))?;
+    let policy = &cfg.retry;
+    let mut retry = 0;
     
+    loop {
+        let failure = match openai_attempt(http, audio, api_key, cfg).await {
+            Ok(transcript) => return Ok(transcript),
+            Err(failure) => failure,
+        };
+        // A timed-out upload already used the whole request budget; don't multiply it
+        if !failure.transient || matches!(failure.error, AsrError::Timeout(_)) || retry >= policy.max_retries {
+            return Err(failure);
+        }
+        let delay = match failure.retry_after {
+            Some(after) if after > policy.max_delay => {
+                log::warn!("OpenAI asked to retry after {}s; giving up", after.as_secs());
+                return Err(failure);
+            }
+            Some(after) => after,
+            None => policy.backoff(retry),
+        };
+        retry += 1;
+        log::warn!(
+            "OpenAI transcription failed ({}); retry {}/{} in {:.1}s",
+            failure.error,
+            retry,
+            policy.max_retries,
+            delay.as_secs_f64()
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(feature = "asr")]
+async fn openai_attempt(
+    http: &reqwest::Client,
+    audio: &AudioInput,
+    api_key: &str,
+    cfg: &AsrConfig,
+) -> Result<Transcript, OpenAiFailure> {
     let form = reqwest::multipart::Form::new()
         .text("model", "whisper-1")
-        .text("response_format", "json")
+        .text("response_format", "verbose_json")
         .part(
             "file", 
-            reqwest::multipart::Part::bytes(audio_bytes.to_vec())
-                .file_name("audio.wav")
-                .mime_str("audio/wav")?
+            reqwest::multipart::Part::bytes(audio.bytes.clone())
+                .file_name(audio.file_name.clone())
+                .mime_str(&audio.mime)
+                .map_err(|e| OpenAiFailure::fatal(AsrError::AudioFormat(format!("invalid content type {}: {}", audio.mime, e))))?
         );
     
-    let response = client
+    let response = http
         .post("https://api.openai.com/v1/audio/transcriptions")
         .header("Authorization", format!("Bearer {}", api_key))
         .multipart(form)
         .send()
         .await
-        .map_err(|e| anyhow!("Failed to send request to OpenAI: {}", e))?;
+        .map_err(|e| OpenAiFailure::transient(send_error(cfg, "OpenAI", e), None))?;
     
-    if !response.status().is_success() {
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = parse_retry_after(&response);
         let error_text = response.text().await.unwrap_or_default();
-        return Err(anyhow!("OpenAI API error: {}", error_text));
+        return Err(match status {
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => OpenAiFailure::fatal(AsrError::Unauthorized(error_text)),
+            reqwest::StatusCode::PAYLOAD_TOO_LARGE => OpenAiFailure::fatal(AsrError::TooLarge(error_text)),
+            reqwest::StatusCode::BAD_REQUEST => OpenAiFailure::fatal(AsrError::AudioFormat(error_text)),
+            _ => {
+                let error = AsrError::Backend(format!("OpenAI API returned {}: {}", status, error_text));
+                if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+                    OpenAiFailure::transient(error, retry_after)
+                } else {
+                    OpenAiFailure::fatal(error)
+                }
+            }
+        });
     }
     
     let whisper_response: WhisperResponse = response
         .json()
         .await
-        .map_err(|e| anyhow!("Failed to parse OpenAI response: {}", e))?;
+        .map_err(|e| {
+            if e.is_timeout() {
+                OpenAiFailure::transient(AsrError::Timeout(cfg.timeout), None)
+            } else {
+                OpenAiFailure::fatal(AsrError::Backend(format!("failed to parse OpenAI response: {}", e)))
+            }
+        })?;
     
-    Ok(whisper_response.text)
+    Ok(whisper_response.into())
 }
 
-#[cfg(feature = "realtime")]
-async fn call_local_whisper_api(audio_bytes: &[u8]) -> Result<String> {
-    // Try common local Whisper API endpoints
-    let endpoints = [
-        "http://localhost:8000/transcribe",
-        "http://localhost:5000/transcribe",
-        "http://127.0.0.1:8000/transcribe",
-    ];
-    
-    let client = reqwest::Client::new();
+// Delay-seconds form only; an HTTP-date falls back to the computed backoff
+#[cfg(feature = "asr")]
+fn parse_retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[cfg(feature = "asr")]
+async fn call_local_whisper_api(audio: &AudioInput, http: &reqwest::Client, cfg: &AsrConfig) -> Result<Transcript, AsrError> {
+    let endpoints = local_endpoints(cfg.local_url.as_deref());
+    // A server that answered with an error says more than one that could not be reached
+    let mut answered: Option<AsrError> = None;
     
     for endpoint in &endpoints {
         log::info!("Trying local Whisper API at: {}", endpoint);
@@ -106,15 +491,15 @@ async fn call_local_whisper_api(audio_bytes: &[u8]) -> Result<String> {
         let form = reqwest::multipart::Form::new()
             .part(
                 "file", 
-                reqwest::multipart::Part::bytes(audio_bytes.to_vec())
-                    .file_name("audio.wav")
-                    .mime_str("audio/wav").unwrap_or_else(|_| {
-                        reqwest::multipart::Part::bytes(audio_bytes.to_vec())
-                            .file_name("audio.wav")
+                reqwest::multipart::Part::bytes(audio.bytes.clone())
+                    .file_name(audio.file_name.clone())
+                    .mime_str(&audio.mime).unwrap_or_else(|_| {
+                        reqwest::multipart::Part::bytes(audio.bytes.clone())
+                            .file_name(audio.file_name.clone())
                     })
             );
         
-        match client
+        match http
             .post(*endpoint)
             .multipart(form)
             .send()
@@ -124,18 +509,26 @@ async fn call_local_whisper_api(audio_bytes: &[u8]) -> Result<String> {
                 match response.json::<WhisperResponse>().await {
                     Ok(whisper_response) => {
                         log::info!("Successfully used local API at: {}", endpoint);
-                        return Ok(whisper_response.text);
+                        return Ok(whisper_response.into());
                     }
                     Err(e) => {
                         log::warn!("Failed to parse response from {}: {}", endpoint, e);
+                        answered = Some(AsrError::Backend(format!("invalid response from {}: {}", endpoint, e)));
                         continue;
                     }
                 }
             }
             Ok(response) => {
-                log::warn!("Local API at {} returned status: {}", endpoint, response.status());
+                let status = response.status();
+                log::warn!("Local API at {} returned status: {}", endpoint, status);
+                answered = Some(match status {
+                    reqwest::StatusCode::PAYLOAD_TOO_LARGE => AsrError::TooLarge(format!("{} returned {}", endpoint, status)),
+                    _ => AsrError::Backend(format!("{} returned {}", endpoint, status)),
+                });
                 continue;
             }
+            // A server that accepted the upload but never answered is not worth retrying elsewhere
+            Err(e) if e.is_timeout() && !e.is_connect() => return Err(AsrError::Timeout(cfg.timeout)),
             Err(e) => {
                 log::debug!("Failed to connect to {}: {}", endpoint, e);
                 continue;
@@ -143,16 +536,84 @@ async fn call_local_whisper_api(audio_bytes: &[u8]) -> Result<String> {
         }
     }
     
-    Err(anyhow!("No local Whisper API found. Tried: {:?}\nTo use local API, start a Whisper server on one of these endpoints.", endpoints))
+    Err(answered.unwrap_or_else(|| AsrError::Unreachable(format!(
+        "no local Whisper API found. Tried: {:?}\nTo use local API, start a Whisper server on one of these endpoints.",
+        endpoints
+    ))))
+}
+
+#[cfg(feature = "asr")]
+fn http_client(cfg: &AsrConfig) -> Result<reqwest::Client, AsrError> {
+    reqwest::Client::builder()
+        .connect_timeout(cfg.connect_timeout)
+        .timeout(cfg.timeout)
+        .build()
+        .map_err(|e| AsrError::Backend(format!("failed to build HTTP client: {}", e)))
+}
+
+#[cfg(feature = "asr")]
+fn send_error(cfg: &AsrConfig, backend: &str, e: reqwest::Error) -> AsrError {
+    if e.is_timeout() {
+        AsrError::Timeout(cfg.timeout)
+    } else {
+        AsrError::Unreachable(format!("failed to send request to {}: {}", backend, e))
+    }
+}
+
+// Use the configured endpoint, or try common local Whisper API endpoints
+#[cfg(feature = "asr")]
+fn local_endpoints(url: Option<&str>) -> Vec<&str> {
+    match url {
+        Some(url) => vec![url],
+        None => vec![
+            "http://localhost:8000/transcribe",
+            "http://localhost:5000/transcribe",
+            "http://127.0.0.1:8000/transcribe",
+        ],
+    }
+}
+
+#[cfg(feature = "ui")]
+impl AsrClient {
+    /// Probe the configured backend without sending audio. Any HTTP answer from a local
+    /// server counts as reachable; OpenAI must also accept the API key.
+    pub async fn check_backend(&self) -> Result<String> {
+        let cfg = &self.cfg;
+        let probe_timeout = Duration::from_secs(3);
+
+        if cfg.use_local {
+            let endpoints = local_endpoints(cfg.local_url.as_deref());
+            for endpoint in &endpoints {
+                if self.http.get(*endpoint).timeout(probe_timeout).send().await.is_ok() {
+                    return Ok(format!("Local Whisper API reachable at {}", endpoint));
+                }
+            }
+            return Err(anyhow!("No local Whisper API reachable. Tried: {:?}", endpoints));
+        }
+
+        let api_key = cfg.api_key.as_ref()
+            .ok_or_else(|| anyhow!("OpenAI API key not configured"))?;
+        let response = self.http
+            .get("https://api.openai.com/v1/models")
+            .bearer_auth(api_key)
+            .timeout(probe_timeout)
+            .send()
+            .await
+            .map_err(|e| anyhow!("OpenAI API unreachable: {}", e))?;
+        match response.status() {
+            status if status.is_success() => Ok("OpenAI API reachable".to_string()),
+            reqwest::StatusCode::UNAUTHORIZED => Err(anyhow!("OpenAI API rejected the API key")),
+            status => Err(anyhow!("OpenAI API returned status {}", status)),
+        }
+    }
 }
 
 #[cfg(feature = "realtime")]
 pub mod realtime {
     use super::*;
     use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-    use std::sync::{Arc, Mutex};
 
-    pub fn record_and_transcribe(cfg: &AsrConfig, seconds: u32) -> Result<String> {
+    pub fn record_and_transcribe(client: &AsrClient, seconds: u32, cancel: &crate::cancel::CancelToken) -> Result<String> {
         let host = cpal::default_host();
         let device = host.default_input_device().ok_or(anyhow!("no input device"))?;
         let mut supported = device.supported_input_configs().map_err(|e| anyhow!(e))?;
@@ -177,7 +638,13 @@ pub mod realtime {
         
         println!("Recording for {} seconds...", seconds);
         stream.play()?;
-        std::thread::sleep(std::time::Duration::from_secs(seconds as u64));
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(seconds as u64) {
+            if cancel.is_cancelled() {
+                return Err(AsrError::Cancelled.into());
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
         drop(stream);
         let samples = buf.lock().unwrap().clone();
         println!("Recording complete. Processing...");
@@ -211,7 +678,7 @@ pub mod realtime {
         }
         
         // Transcribe the temporary WAV file using the API
-        let result = transcribe_wav(temp_file.to_str().unwrap(), cfg);
+        let result = client.transcribe_wav(temp_file.to_str().unwrap(), cancel);
         
         // Clean up temporary file
         let _ = std::fs::remove_file(temp_file);
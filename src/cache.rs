@@ -0,0 +1,419 @@
+//! Translation cache in front of the model: an in-memory LRU, optionally backed by an
+//! append-only JSON-lines file so entries survive restarts.
+
+use crate::config::CacheSection;
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Bytes hashed from each end of the model file for its fingerprint
+const FINGERPRINT_SAMPLE: u64 = 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+struct DiskEntry {
+    key: String,
+    text: String,
+}
+
+#[cfg_attr(not(feature = "ui"), allow(dead_code))]
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+struct TranslationCache {
+    enabled: bool,
+    capacity: usize,
+    // key -> (translation, last use); `order` maps last use back to the key for eviction
+    entries: HashMap<String, (String, u64)>,
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+// The JSON-lines store, locked apart from the cache so lookups never wait on the file
+#[derive(Default)]
+struct DiskStore {
+    path: Option<PathBuf>,
+    // Lines in the file, live or not, to tell when it needs compacting
+    lines: usize,
+}
+
+impl TranslationCache {
+    fn new(enabled: bool, capacity: usize) -> Self {
+        Self {
+            enabled,
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<String> {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.entries.get_mut(key) {
+            Some((text, used)) => {
+                self.order.remove(used);
+                *used = tick;
+                self.order.insert(tick, key.to_string());
+                self.hits += 1;
+                Some(text.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    // Insert without touching the disk store
+    fn put(&mut self, key: String, text: String) {
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.get(&key) {
+            self.order.remove(used);
+        }
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (text, self.tick));
+
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else { break };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    // Live entries, least recently used first
+    fn live(&self) -> Vec<(String, String)> {
+        self.order.values().map(|key| (key.clone(), self.entries[key].0.clone())).collect()
+    }
+
+    #[cfg(feature = "ui")]
+    fn clear(&mut self) -> usize {
+        let cleared = self.entries.len();
+        self.entries.clear();
+        self.order.clear();
+        cleared
+    }
+
+    #[cfg(feature = "ui")]
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            enabled: self.enabled,
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<TranslationCache> =
+        Mutex::new(TranslationCache::new(true, CacheSection::default().capacity));
+    static ref DISK: Mutex<DiskStore> = Mutex::new(DiskStore::default());
+    static ref FINGERPRINTS: Mutex<HashMap<PathBuf, Fingerprint>> = Mutex::new(HashMap::new());
+}
+
+// A model fingerprint and the file state it was computed for
+struct Fingerprint {
+    len: u64,
+    modified: Option<SystemTime>,
+    value: String,
+}
+
+/// Apply the `[cache]` settings, loading the disk store when one is configured.
+pub fn configure(section: &CacheSection) -> Result<()> {
+    let mut cache = TranslationCache::new(section.enabled, section.capacity);
+    let mut disk = DiskStore { path: section.path.as_ref().map(PathBuf::from), lines: 0 };
+
+    if let (true, Some(path)) = (section.enabled, &disk.path) {
+        disk.lines = load_disk(&mut cache, path)?;
+        log::info!("Loaded {} cached translations from {}", cache.entries.len(), path.display());
+        // The store is append-only; rewrite it once stale and duplicate lines pile up
+        if disk.lines > cache.entries.len() * 2 {
+            let live = cache.live();
+            compact(&live, path)?;
+            disk.lines = live.len();
+        }
+    }
+
+    let mut current = DISK.lock().unwrap();
+    *CACHE.lock().unwrap() = cache;
+    *current = disk;
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    CACHE.lock().unwrap().enabled
+}
+
+pub fn lookup(key: &str) -> Option<String> {
+    let mut cache = CACHE.lock().unwrap();
+    if !cache.enabled {
+        return None;
+    }
+    cache.get(key)
+}
+
+pub fn store(key: &str, text: &str) {
+    let capacity = {
+        let mut cache = CACHE.lock().unwrap();
+        if !cache.enabled {
+            return;
+        }
+        cache.put(key.to_string(), text.to_string());
+        cache.capacity
+    };
+
+    // The file is written with only the disk lock held, so other requests can still use
+    // the cache meanwhile
+    let mut disk = DISK.lock().unwrap();
+    let Some(path) = disk.path.clone() else { return };
+    if let Err(e) = append_disk(&path, key, text) {
+        log::warn!("Failed to write translation cache {}: {}", path.display(), e);
+        return;
+    }
+    disk.lines += 1;
+    // Evicted and repeated keys keep their lines; rewrite the file before it grows
+    // without bound on a long-running server
+    if disk.lines > capacity * 2 {
+        let live = CACHE.lock().unwrap().live();
+        match compact(&live, &path) {
+            Ok(()) => disk.lines = live.len(),
+            Err(e) => log::warn!("{}", e),
+        }
+    }
+}
+
+/// Drop every entry, in memory and on disk. Returns how many were in memory.
+#[cfg(feature = "ui")]
+pub fn clear() -> Result<usize> {
+    // Held throughout so no store appends between clearing memory and the file
+    let mut disk = DISK.lock().unwrap();
+    let cleared = CACHE.lock().unwrap().clear();
+    if let Some(path) = &disk.path {
+        File::create(path).map_err(|e| anyhow!("Failed to truncate translation cache {}: {}", path.display(), e))?;
+        disk.lines = 0;
+    }
+    log::info!("Cleared {} cached translations", cleared);
+    Ok(cleared)
+}
+
+#[cfg(feature = "ui")]
+pub fn stats() -> CacheStats {
+    CACHE.lock().unwrap().stats()
+}
+
+/// Cache key for one request. Whitespace is collapsed so that re-typed or re-segmented
+/// input still hits; case and punctuation are kept since they change the translation.
+pub fn key(model_fingerprint: &str, direction: &str, sampling: &str, context: &str, input: &str) -> String {
+    let input = input.split_whitespace().collect::<Vec<_>>().join(" ");
+    // Unit separators keep the fields unambiguous whatever the input contains
+    format!("{}\u{1f}{}\u{1f}{}\u{1f}{}\u{1f}{}", model_fingerprint, direction, sampling, context, input)
+}
+
+/// Fingerprint of a model file: its size plus an FNV-1a hash of the first and last
+/// megabyte, which cover the GGUF header and the tail of the tensor data. Memoized per
+/// path until the file's size or modification time changes.
+///
+/// This is a sample, not a content hash. Hashing a multi-gigabyte file in full would
+/// stall the first translation for seconds on small devices, every time the file is
+/// touched. The price is that two models of the same size whose first and last megabyte
+/// match, say a fine-tune whose only changes fall in the middle tensors with its
+/// metadata left alone, share cache entries; clear the cache after such a swap.
+pub fn model_fingerprint(path: &Path) -> Result<String> {
+    let meta = std::fs::metadata(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let (len, modified) = (meta.len(), meta.modified().ok());
+    if let Some(fp) = FINGERPRINTS.lock().unwrap().get(path) {
+        if fp.len == len && fp.modified == modified {
+            return Ok(fp.value.clone());
+        }
+    }
+
+    let mut file = File::open(path).map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let mut hash = Fnv::new();
+    hash.write(&len.to_le_bytes());
+    let mut buf = Vec::new();
+    (&mut file).take(FINGERPRINT_SAMPLE).read_to_end(&mut buf)?;
+    if len > FINGERPRINT_SAMPLE {
+        file.seek(SeekFrom::Start(len.saturating_sub(FINGERPRINT_SAMPLE).max(FINGERPRINT_SAMPLE)))?;
+        file.read_to_end(&mut buf)?;
+    }
+    hash.write(&buf);
+
+    let value = format!("{:016x}", hash.0);
+    FINGERPRINTS.lock().unwrap().insert(path.to_path_buf(), Fingerprint { len, modified, value: value.clone() });
+    Ok(value)
+}
+
+// FNV-1a; unlike std's `DefaultHasher` it is stable across builds
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+fn load_disk(cache: &mut TranslationCache, path: &Path) -> Result<usize> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(anyhow!("Failed to open translation cache {}: {}", path.display(), e)),
+    };
+    let mut lines = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| anyhow!("Failed to read translation cache {}: {}", path.display(), e))?;
+        lines += 1;
+        // A torn last line from a crash is skipped rather than failing startup
+        match serde_json::from_str::<DiskEntry>(&line) {
+            Ok(entry) => cache.put(entry.key, entry.text),
+            Err(e) => log::warn!("Skipping bad line {} in {}: {}", lines, path.display(), e),
+        }
+    }
+    Ok(lines)
+}
+
+fn append_disk(path: &Path, key: &str, text: &str) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let line = serde_json::to_string(&DiskEntry { key: key.to_string(), text: text.to_string() })?;
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
+
+// Rewrite the store with the live entries, oldest first so the reload keeps LRU order
+fn compact(live: &[(String, String)], path: &Path) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp)
+            .map_err(|e| anyhow!("Failed to write translation cache {}: {}", tmp.display(), e))?;
+        for (key, text) in live {
+            let line = serde_json::to_string(&DiskEntry { key: key.clone(), text: text.clone() })?;
+            writeln!(file, "{}", line)?;
+        }
+    }
+    std::fs::rename(&tmp, path)
+        .map_err(|e| anyhow!("Failed to replace translation cache {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(cache: &TranslationCache) -> Vec<&str> {
+        cache.order.values().map(String::as_str).collect()
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cache-test-{}-{}.jsonl", std::process::id(), name))
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let mut cache = TranslationCache::new(true, 2);
+        cache.put("a".to_string(), "A".to_string());
+        cache.put("b".to_string(), "B".to_string());
+        assert_eq!(cache.get("a").as_deref(), Some("A"));
+        cache.put("c".to_string(), "C".to_string());
+        assert_eq!(keys(&cache), ["a", "c"]);
+        assert_eq!(cache.get("b"), None);
+
+        // Replacing an entry counts as a use
+        cache.put("a".to_string(), "A2".to_string());
+        cache.put("d".to_string(), "D".to_string());
+        assert_eq!(keys(&cache), ["a", "d"]);
+        assert_eq!(cache.live(), [("a".to_string(), "A2".to_string()), ("d".to_string(), "D".to_string())]);
+        assert_eq!((cache.hits, cache.misses), (1, 1));
+    }
+
+    #[test]
+    fn reloads_in_lru_order_and_skips_bad_lines() {
+        let path = scratch("reload");
+        let mut live = TranslationCache::new(true, 10);
+        for key in ["a", "b", "c"] {
+            live.put(key.to_string(), key.to_uppercase());
+        }
+        live.get("a");
+        compact(&live.live(), &path).unwrap();
+        append_disk(&path, "b", "B2").unwrap();
+        let mut text = std::fs::read_to_string(&path).unwrap();
+        text.push_str("{\"key\": \"torn");
+        std::fs::write(&path, text).unwrap();
+
+        let mut reloaded = TranslationCache::new(true, 10);
+        assert_eq!(load_disk(&mut reloaded, &path).unwrap(), 5);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(keys(&reloaded), ["c", "a", "b"]);
+        assert_eq!(reloaded.get("b").as_deref(), Some("B2"));
+        assert_eq!(load_disk(&mut reloaded, &path).unwrap(), 0);
+    }
+
+    // The only test using the shared cache
+    #[test]
+    fn stores_compacts_and_survives_a_restart() {
+        let path = scratch("store");
+        let section = CacheSection { enabled: true, capacity: 2, path: Some(path.display().to_string()) };
+        configure(&section).unwrap();
+        for (key, text) in [("a", "A"), ("b", "B"), ("a", "A"), ("c", "C")] {
+            store(key, text);
+        }
+        let lines = |path: &Path| std::fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(&path), 4);
+        // The fifth line is over twice the capacity, so only the live entries are kept
+        store("d", "D");
+        assert_eq!(lines(&path), 2);
+        assert_eq!(lookup("b"), None);
+
+        configure(&section).unwrap();
+        assert_eq!(lookup("c").as_deref(), Some("C"));
+        assert_eq!(lookup("d").as_deref(), Some("D"));
+        assert_eq!(lookup("a"), None);
+
+        configure(&CacheSection { enabled: false, ..section }).unwrap();
+        store("e", "E");
+        assert_eq!(lookup("e"), None);
+        assert_eq!(lines(&path), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keys_collapse_whitespace_only() {
+        assert_eq!(key("m", "en-es", "t0", "", " Hello \n world "), key("m", "en-es", "t0", "", "Hello world"));
+        assert_ne!(key("m", "en-es", "t0", "", "Hello world"), key("m", "en-es", "t0", "", "hello world"));
+        assert_ne!(key("m", "en-es", "t0", "", "a"), key("m", "es-en", "t0", "", "a"));
+    }
+
+    #[test]
+    fn fingerprints_change_with_the_model_file() {
+        let path = scratch("model");
+        std::fs::write(&path, vec![1u8; 4096]).unwrap();
+        let first = model_fingerprint(&path).unwrap();
+        assert_eq!(model_fingerprint(&path).unwrap(), first);
+        std::fs::write(&path, vec![2u8; 4097]).unwrap();
+        assert_ne!(model_fingerprint(&path).unwrap(), first);
+        std::fs::remove_file(&path).unwrap();
+        assert!(model_fingerprint(&path).is_err());
+    }
+}
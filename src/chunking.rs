@@ -0,0 +1,261 @@
+//! Splitting long WAV recordings into uploads that fit the Whisper size limit.
+//!
+//! Audio is downmixed to mono 16-bit PCM and cut at the quietest frame near each target
+//! size. Neighbouring chunks overlap by `OVERLAP_SECS` around the cut so a word that
+//! straddles it is heard in full by at least one of them.
+
+use crate::asr::{AsrError, AudioInput, Segment, Transcript};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use std::io::Cursor;
+
+/// Chunks aim for this share of the upload limit, leaving room for the overlap.
+const TARGET_FRACTION: f64 = 0.9;
+/// How far back from the target end to look for a quiet cut point, at most a quarter
+/// of the chunk
+const SEARCH_SECS: f64 = 30.0;
+const OVERLAP_SECS: f64 = 1.0;
+const FRAME_MS: u32 = 30;
+const WAV_HEADER_BYTES: usize = 44;
+
+/// One upload, plus where it sits in the original recording.
+pub struct Chunk {
+    pub audio: AudioInput,
+    /// Start of this chunk's audio in the recording, in seconds
+    pub offset: f64,
+    /// The part of the recording this chunk's transcript is kept for, `[from, until)`
+    pub keep_from: f64,
+    pub keep_until: f64,
+}
+
+/// Split a WAV file into chunks of at most `max_bytes` each.
+pub fn split_wav(bytes: &[u8], max_bytes: usize) -> Result<Vec<Chunk>, AsrError> {
+    let mut reader = WavReader::new(Cursor::new(bytes))
+        .map_err(|e| AsrError::AudioFormat(format!("not a valid WAV file: {}", e)))?;
+    let spec = reader.spec();
+    let samples = read_mono(&mut reader)?;
+    let rate = spec.sample_rate as usize;
+
+    let target = ((max_bytes as f64 * TARGET_FRACTION) as usize).saturating_sub(WAV_HEADER_BYTES) / 2;
+    let overlap = (OVERLAP_SECS * rate as f64) as usize;
+    let search = ((SEARCH_SECS * rate as f64) as usize).min(target / 4);
+    if target <= 2 * overlap + rate {
+        return Err(AsrError::TooLarge(format!("upload limit of {} bytes is too small to split audio", max_bytes)));
+    }
+
+    // Cut points, each at the quietest frame in the search window before the target end
+    let mut cuts = vec![0];
+    let mut start = 0;
+    while samples.len() - start > target {
+        let ideal = start + target - overlap;
+        let window_start = ideal.saturating_sub(search).max(start + overlap + 1);
+        let cut = quietest_point(&samples[window_start..ideal], spec.sample_rate) + window_start;
+        cuts.push(cut);
+        start = cut;
+    }
+    cuts.push(samples.len());
+
+    let secs = |sample: usize| sample as f64 / rate as f64;
+    let mut chunks = Vec::with_capacity(cuts.len() - 1);
+    for (i, pair) in cuts.windows(2).enumerate() {
+        let (from, until) = (pair[0], pair[1]);
+        let begin = from.saturating_sub(overlap / 2);
+        let end = (until + overlap / 2).min(samples.len());
+        chunks.push(Chunk {
+            audio: encode(&samples[begin..end], spec.sample_rate, i)?,
+            offset: secs(begin),
+            keep_from: secs(from),
+            keep_until: if until == samples.len() { f64::INFINITY } else { secs(until) },
+        });
+    }
+    log::info!("Split {:.0}s of audio into {} chunks", secs(samples.len()), chunks.len());
+    Ok(chunks)
+}
+
+/// Join chunk transcripts in order, shifting segment times back onto the recording's
+/// timeline and dropping segments that belong to a neighbour's side of the overlap.
+pub fn stitch(parts: Vec<(Chunk, Transcript)>) -> Transcript {
+    let mut texts = Vec::new();
+    let mut segments = Vec::new();
+
+    for (chunk, transcript) in parts {
+        if transcript.segments.is_empty() {
+            // Backends without segments only give us the text; the overlap is mostly silence
+            texts.push(transcript.text.trim().to_string());
+            continue;
+        }
+        let mut kept = Vec::new();
+        for segment in transcript.segments {
+            let start = segment.start + chunk.offset;
+            let end = segment.end + chunk.offset;
+            let middle = (start + end) / 2.0;
+            if middle >= chunk.keep_from && middle < chunk.keep_until {
+                kept.push(segment.text.trim().to_string());
+                segments.push(Segment { start, end, text: segment.text });
+            }
+        }
+        texts.push(kept.join(" "));
+    }
+
+    texts.retain(|t| !t.is_empty());
+    Transcript { text: texts.join(" "), segments }
+}
+
+fn read_mono(reader: &mut WavReader<Cursor<&[u8]>>) -> Result<Vec<i16>, AsrError> {
+    let spec = reader.spec();
+    let bad = |e: hound::Error| AsrError::AudioFormat(format!("failed to read WAV samples: {}", e));
+    let interleaved: Vec<i32> = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, _) => reader
+            .samples::<f32>()
+            .map(|s| s.map(|v| (v.clamp(-1.0, 1.0) * 32767.0) as i32))
+            .collect::<Result<_, _>>()
+            .map_err(bad)?,
+        (SampleFormat::Int, bits) if bits <= 16 => {
+            let shift = 16 - bits;
+            reader.samples::<i32>().map(|s| s.map(|v| v << shift)).collect::<Result<_, _>>().map_err(bad)?
+        }
+        (SampleFormat::Int, bits) => {
+            let shift = bits - 16;
+            reader.samples::<i32>().map(|s| s.map(|v| v >> shift)).collect::<Result<_, _>>().map_err(bad)?
+        }
+    };
+
+    let channels = spec.channels.max(1) as usize;
+    Ok(interleaved
+        .chunks(channels)
+        .map(|frame| (frame.iter().sum::<i32>() / frame.len() as i32) as i16)
+        .collect())
+}
+
+// Offset of the middle of the lowest-energy frame, the latest one on ties
+fn quietest_point(samples: &[i16], sample_rate: u32) -> usize {
+    let frame_len = (sample_rate * FRAME_MS / 1000).max(1) as usize;
+    samples
+        .chunks(frame_len)
+        .enumerate()
+        .rev()
+        .min_by_key(|(_, frame)| frame.iter().map(|&s| (s as i64) * (s as i64)).sum::<i64>() / frame.len() as i64)
+        .map(|(i, frame)| i * frame_len + frame.len() / 2)
+        .unwrap_or(samples.len() / 2)
+}
+
+fn encode(samples: &[i16], sample_rate: u32, index: usize) -> Result<AudioInput, AsrError> {
+    let spec = WavSpec { channels: 1, sample_rate, bits_per_sample: 16, sample_format: SampleFormat::Int };
+    let mut cursor = Cursor::new(Vec::with_capacity(WAV_HEADER_BYTES + samples.len() * 2));
+    {
+        let failed = |e: hound::Error| AsrError::AudioFormat(format!("failed to encode audio chunk: {}", e));
+        let mut writer = WavWriter::new(&mut cursor, spec).map_err(failed)?;
+        for &sample in samples {
+            writer.write_sample(sample).map_err(failed)?;
+        }
+        writer.finalize().map_err(failed)?;
+    }
+    Ok(AudioInput {
+        bytes: cursor.into_inner(),
+        file_name: format!("chunk-{}.wav", index),
+        mime: "audio/wav".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 8000;
+
+    fn wav(channels: u16, frames: &[i16]) -> Vec<u8> {
+        let spec = WavSpec { channels, sample_rate: RATE, bits_per_sample: 16, sample_format: SampleFormat::Int };
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut cursor, spec).unwrap();
+        for &sample in frames {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        cursor.into_inner()
+    }
+
+    fn samples(audio: &AudioInput) -> (WavSpec, Vec<i16>) {
+        let mut reader = WavReader::new(Cursor::new(&audio.bytes)).unwrap();
+        (reader.spec(), reader.samples::<i16>().map(Result::unwrap).collect())
+    }
+
+    fn chunk(offset: f64, keep_from: f64, keep_until: f64) -> Chunk {
+        let audio = AudioInput { bytes: Vec::new(), file_name: String::new(), mime: String::new() };
+        Chunk { audio, offset, keep_from, keep_until }
+    }
+
+    fn segment(start: f64, end: f64, text: &str) -> Segment {
+        Segment { start, end, text: text.to_string() }
+    }
+
+    #[test]
+    fn short_audio_is_one_downmixed_chunk() {
+        let stereo: Vec<i16> = (0..RATE).flat_map(|_| [1000, 3000]).collect();
+        let chunks = split_wav(&wav(2, &stereo), 1 << 20).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!((chunks[0].offset, chunks[0].keep_from, chunks[0].keep_until), (0.0, 0.0, f64::INFINITY));
+        let (spec, mono) = samples(&chunks[0].audio);
+        assert_eq!((spec.channels, spec.sample_rate), (1, RATE));
+        assert_eq!(mono.len(), RATE as usize);
+        assert!(mono.iter().all(|&s| s == 2000));
+    }
+
+    #[test]
+    fn cuts_long_audio_at_a_quiet_point_within_the_limit() {
+        // 7.5s of a loud square wave with a short pause at 2.1s
+        let pause = 16800..17600;
+        let audio: Vec<i16> = (0..60_000)
+            .map(|i| if pause.contains(&i) { 0 } else if i % 20 < 10 { 8000 } else { -8000 })
+            .collect();
+        let max_bytes = 60_000;
+        let chunks = split_wav(&wav(1, &audio), max_bytes).unwrap();
+
+        assert!(chunks.len() > 2);
+        assert!(chunks.iter().all(|chunk| chunk.audio.bytes.len() <= max_bytes));
+        let first_cut = chunks[0].keep_until * RATE as f64;
+        assert!(pause.contains(&(first_cut as usize)), "cut at {}", first_cut);
+        assert_eq!(chunks[0].keep_from, 0.0);
+        assert_eq!(chunks.last().unwrap().keep_until, f64::INFINITY);
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].keep_until, pair[1].keep_from);
+            // Half the overlap before the cut is heard again by the next chunk
+            assert!((pair[1].keep_from - pair[1].offset - OVERLAP_SECS / 2.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(matches!(split_wav(b"not a wav", 1 << 20), Err(AsrError::AudioFormat(_))));
+        let audio = wav(1, &vec![100; 3 * RATE as usize]);
+        assert!(matches!(split_wav(&audio, 4096), Err(AsrError::TooLarge(_))));
+    }
+
+    #[test]
+    fn stitch_keeps_each_overlapping_segment_once() {
+        let first = Transcript {
+            text: "one two".to_string(),
+            segments: vec![segment(0.0, 4.0, " one"), segment(8.0, 10.4, " two")],
+        };
+        // The second chunk starts half a second before the cut at 10s
+        let second = Transcript {
+            text: "two three".to_string(),
+            segments: vec![segment(0.0, 0.9, " two"), segment(1.0, 3.0, " three")],
+        };
+        let transcript = stitch(vec![(chunk(0.0, 0.0, 10.0), first), (chunk(9.5, 10.0, f64::INFINITY), second)]);
+        assert_eq!(transcript.text, "one two three");
+        let times: Vec<(f64, f64)> = transcript.segments.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(times, [(0.0, 4.0), (8.0, 10.4), (10.5, 12.5)]);
+    }
+
+    #[test]
+    fn stitch_joins_text_without_segments() {
+        let text = |text: &str| Transcript { text: text.to_string(), segments: Vec::new() };
+        let parts = vec![
+            (chunk(0.0, 0.0, 10.0), text(" Hello there. ")),
+            (chunk(9.5, 10.0, 20.0), text("")),
+            (chunk(19.5, 20.0, f64::INFINITY), text("Bye.")),
+        ];
+        let transcript = stitch(parts);
+        assert_eq!(transcript.text, "Hello there. Bye.");
+        assert!(transcript.segments.is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+//! Cleanup of raw model output before it is used as a translation: special tokens and
+//! llama.cpp log lines that end up on stdout, preambles such as "Here is the
+//! translation:", notes after the translation, wrapping quotes, and degenerate
+//! repetition.
+//!
+//! Each step compares against the source text, so a source that itself starts with a
+//! label, is quoted or repeats a phrase keeps that in its translation.
+
+/// Special tokens of the supported model families and the end-of-text notice of older
+/// llama.cpp builds. Nothing after one of these belongs to the translation.
+const END_MARKERS: &[&str] = &[
+    "<end_of_turn>",
+    "<start_of_turn>",
+    "<eos>",
+    "</s>",
+    "<|eot_id|>",
+    "<|start_header_id|>",
+    "<|end_of_text|>",
+    "<|im_end|>",
+    "<|im_start|>",
+    "<|endoftext|>",
+    "<|end|>",
+    "[end of text]",
+];
+
+/// Starts of llama.cpp log lines, which reach stdout when a wrapper merges stderr in.
+/// The perf summary comes last, so everything from the first one on is dropped.
+const LOG_PREFIXES: &[&str] = &["llama_", "ggml_", "common_perf_", "main: "];
+
+/// Words that mark a first line ending in a colon as a preamble, lowercased
+const PREAMBLE_WORDS: &[&str] = &["translat", "traduc", "in english", "in spanish", "en inglés", "en español"];
+
+/// A preamble label is only looked for this far into the output
+const MAX_PREAMBLE_CHARS: usize = 100;
+
+/// Openings of notes models add after the translation, lowercased
+const NOTE_OPENERS: &[&str] = &[
+    "note",
+    "nota",
+    "translator's note",
+    "translation note",
+    "explanation",
+    "explicación",
+    "literal translation",
+    "literally",
+    "literalmente",
+    "alternatively",
+    "alternative",
+    "this translation",
+    "esta traducción",
+    "i translated",
+    "he traducido",
+];
+
+const QUOTES: &[(char, char)] = &[('"', '"'), ('“', '”'), ('„', '“'), ('«', '»'), ('\'', '\''), ('‘', '’')];
+
+/// Phrases of this many words or fewer are checked for repetition
+const MAX_REPEAT_WORDS: usize = 32;
+
+/// Clean `output` produced for `source`. May return an empty string when nothing
+/// but chatter was generated.
+pub fn clean(output: &str, source: &str) -> String {
+    let mut text = output;
+    let markers = END_MARKERS.iter().filter(|m| !source.contains(*m));
+    if let Some(end) = markers.filter_map(|m| text.find(m)).min() {
+        text = &text[..end];
+    }
+    if let Some(end) = log_start(text) {
+        text = &text[..end];
+    }
+    let mut text = text.trim();
+
+    if label_end(source).is_none() {
+        text = strip_preamble(text);
+    }
+    if !NOTE_OPENERS.iter().any(|opener| source.lines().any(|line| opens_note(line, opener))) {
+        text = strip_notes(text, source);
+    }
+    text = strip_quotes(text, source);
+
+    if repetition(source).is_none() {
+        if let Some(end) = repetition(text) {
+            log::warn!("Model output repeats itself; keeping {:?}", &text[..end]);
+            text = text[..end].trim_end_matches([',', ';', ' ']);
+        }
+    }
+    let cleaned = text.trim();
+    if cleaned != output.trim() {
+        log::debug!("Cleaned model output {:?} to {:?}", output, cleaned);
+    }
+    cleaned.to_string()
+}
+
+// Byte offset of the first line that is a llama.cpp log line
+fn log_start(text: &str) -> Option<usize> {
+    let mut at = 0;
+    for line in text.split_inclusive('\n') {
+        if LOG_PREFIXES.iter().any(|p| line.starts_with(p)) {
+            return Some(at);
+        }
+        at += line.len();
+    }
+    None
+}
+
+// Position of the colon when `text` opens with a short label, as preambles do
+fn label_end(text: &str) -> Option<usize> {
+    let first_line = text.lines().next().unwrap_or_default();
+    let colon = first_line.find(':')?;
+    (first_line[..colon].chars().count() <= MAX_PREAMBLE_CHARS).then_some(colon)
+}
+
+// "Here is the translation:", "**Translation:**", "Sure! Here's the English version:"
+fn strip_preamble(text: &str) -> &str {
+    let Some(colon) = label_end(text) else { return text };
+    let label = text[..colon].to_lowercase();
+    if !PREAMBLE_WORDS.iter().any(|w| label.contains(w)) {
+        return text;
+    }
+    text[colon + 1..].trim_start_matches('*').trim_start()
+}
+
+// Whether `line` starts with `opener` as a whole word, ignoring list and markup marks
+fn opens_note(line: &str, opener: &str) -> bool {
+    let line = line.trim_start_matches(|c: char| c.is_whitespace() || matches!(c, '*' | '_' | '(' | '[' | '-' | '#' | '>'));
+    let Some(head) = line.get(..opener.len()) else { return false };
+    head.to_lowercase() == opener && !line[opener.len()..].starts_with(char::is_alphanumeric)
+}
+
+// Drop notes after the translation: a paragraph the single-paragraph source has no
+// counterpart for, a line opening with a note word, or a trailing "(Note: ...)"
+fn strip_notes<'a>(text: &'a str, source: &str) -> &'a str {
+    let mut text = text;
+    if !source.contains("\n\n") {
+        if let Some(end) = text.find("\n\n") {
+            text = &text[..end];
+        }
+    }
+    let mut at = 0;
+    for line in text.split_inclusive('\n') {
+        if at > 0 && NOTE_OPENERS.iter().any(|opener| opens_note(line, opener)) {
+            text = &text[..at];
+            break;
+        }
+        at += line.len();
+    }
+    let text = text.trim_end();
+    if let Some(open) = text.rfind('(').filter(|_| text.ends_with(')') && !source.contains('(')) {
+        if open > 0 && NOTE_OPENERS.iter().any(|opener| opens_note(&text[open..], opener)) {
+            return text[..open].trim_end();
+        }
+    }
+    text
+}
+
+// Remove quotes around the whole output unless the source is quoted too
+fn strip_quotes<'a>(text: &'a str, source: &str) -> &'a str {
+    for &(open, close) in QUOTES {
+        let Some(inner) = text.strip_prefix(open).and_then(|t| t.strip_suffix(close)) else { continue };
+        let source = source.trim();
+        let quoted_source = QUOTES.iter().any(|&(o, c)| source.starts_with(o) && source.ends_with(c));
+        // `"Yes" and "no"` is two quotations, not one wrapped in quotes
+        if quoted_source || inner.contains(open) || inner.contains(close) {
+            return text;
+        }
+        return inner.trim();
+    }
+    text
+}
+
+/// End of the first instance of a phrase that is repeated back to back: three times for
+/// phrases of three words or more, four for shorter ones ("very very very" is fine).
+/// Words are compared ignoring case and surrounding punctuation.
+fn repetition(text: &str) -> Option<usize> {
+    let words: Vec<(usize, String)> = text
+        .split_whitespace()
+        .map(|word| {
+            // `word` is a slice of `text`, so its offset follows from the pointers
+            let end = word.as_ptr() as usize - text.as_ptr() as usize + word.len();
+            let core = word.trim_matches(|c: char| !c.is_alphanumeric());
+            (end, if core.is_empty() { word.to_string() } else { core.to_lowercase() })
+        })
+        .collect();
+
+    for start in 0..words.len() {
+        for len in 1..=MAX_REPEAT_WORDS {
+            let limit = if len >= 3 { 3 } else { 4 };
+            if start + len * limit > words.len() {
+                break;
+            }
+            let unit = &words[start..start + len];
+            let repeats = 1 + (1..limit)
+                .take_while(|k| {
+                    let next = &words[start + k * len..start + (k + 1) * len];
+                    unit.iter().zip(next).all(|(a, b)| a.1 == b.1)
+                })
+                .count();
+            if repeats == limit {
+                return Some(unit[len - 1].0);
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,614 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// File name looked up in the working directory.
+pub const LOCAL_CONFIG_FILE: &str = "gemma-translator.toml";
+
+/// Fully resolved settings shared by the CLI and the UI.
+///
+/// Layers are applied as defaults < config files < env < CLI flags. The file
+/// and default layers are handled here; env and CLI are merged by clap in `main.rs`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub gemma: GemmaSection,
+    pub sampling: SamplingSection,
+    pub asr: AsrSection,
+    pub server: ServerSection,
+    pub languages: LanguageSection,
+    pub jobs: JobsSection,
+    pub timeouts: TimeoutsSection,
+    pub cache: CacheSection,
+    pub memory: MemorySection,
+    pub protect: ProtectSection,
+    pub chunking: ChunkingSection,
+    pub models: ModelsSection,
+    /// Glossary file (CSV or TBX) per language pair, e.g. `es-en = "terms.tbx"`
+    pub glossaries: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GemmaSection {
+    /// Name of a registered model (see `[models]`); when set it picks `model_path`
+    pub model: Option<String>,
+    pub model_path: String,
+    pub n_ctx: usize,
+    /// Answer known phrases from the built-in phrasebook when inference fails.
+    /// Off by default so a broken setup reports errors instead of placeholder output.
+    pub phrasebook_fallback: bool,
+    /// Chat template family: `auto` (from the model's GGUF metadata), `gemma`, `llama3`,
+    /// `chatml` or `mistral`
+    pub template: String,
+    /// TOML file with `prompt`, `response_marker` and `stop`, for other models
+    pub template_file: Option<String>,
+}
+
+impl Default for GemmaSection {
+    fn default() -> Self {
+        Self {
+            model: None,
+            model_path: "models/gemma-2b-it.Q4_K_M.gguf".to_string(),
+            n_ctx: 2048,
+            phrasebook_fallback: false,
+            template: "auto".to_string(),
+            template_file: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SamplingSection {
+    pub max_tokens: usize,
+    pub temperature: f32,
+    // Left to llama.cpp's defaults when unset
+    pub top_k: Option<u32>,
+    pub top_p: Option<f32>,
+    pub min_p: Option<f32>,
+    pub repeat_penalty: Option<f32>,
+    /// Fixed seed for reproducible output; random per run when unset
+    pub seed: Option<u32>,
+    /// Generation ends at the first of these
+    pub stop: Vec<String>,
+}
+
+impl Default for SamplingSection {
+    fn default() -> Self {
+        Self {
+            max_tokens: 256,
+            temperature: 0.1,
+            top_k: None,
+            top_p: None,
+            min_p: None,
+            repeat_penalty: None,
+            seed: None,
+            stop: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AsrBackend {
+    #[default]
+    OpenAi,
+    Local,
+}
+
+impl AsrBackend {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "openai" => Some(AsrBackend::OpenAi),
+            "local" => Some(AsrBackend::Local),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AsrSection {
+    pub backend: AsrBackend,
+    /// Local Whisper endpoint. When unset the well-known localhost ports are probed.
+    pub url: Option<String>,
+    /// OpenAI key; normally supplied via `--api-key` or `OPENAI_API_KEY`
+    #[serde(skip_serializing)]
+    pub api_key: Option<String>,
+    /// Extra attempts after a rate limit, 5xx or connection failure from OpenAI
+    pub max_retries: u32,
+    /// First backoff delay; doubles per attempt, with jitter
+    pub retry_base_ms: u64,
+    /// Upper bound on a single backoff delay, including `Retry-After`
+    pub retry_max_ms: u64,
+    /// Use the local backend while OpenAI is failing
+    pub failover_local: bool,
+    /// WAV files above this size are split at pauses before upload (OpenAI's limit is 25 MB)
+    pub max_upload_mb: usize,
+    /// Chunks of a split file transcribed at the same time
+    pub chunk_concurrency: usize,
+}
+
+impl Default for AsrSection {
+    fn default() -> Self {
+        Self {
+            backend: AsrBackend::default(),
+            url: None,
+            api_key: None,
+            max_retries: 3,
+            retry_base_ms: 500,
+            retry_max_ms: 20_000,
+            failover_local: false,
+            max_upload_mb: 25,
+            chunk_concurrency: 3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServerSection {
+    pub bind: String,
+    pub port: u16,
+    /// HTTP worker threads; inference runs on a separate blocking pool
+    pub workers: usize,
+    /// Bearer token for the endpoints that change server state (switching the model,
+    /// clearing the cache). Without one they only answer clients on loopback.
+    pub admin_token: Option<String>,
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0".to_string(),
+            port: 8080,
+            workers: crate::platform::threads_hint(),
+            admin_token: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct JobsSection {
+    /// Background translation workers. Keep at 1 on devices that fit one model run at a time.
+    pub workers: usize,
+    /// Maximum number of queued (not yet running) jobs
+    pub max_queue: usize,
+    /// How long finished jobs stay available for `GET /jobs/{id}`
+    pub ttl_secs: u64,
+}
+
+impl Default for JobsSection {
+    fn default() -> Self {
+        Self { workers: 1, max_queue: 32, ttl_secs: 3600 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TimeoutsSection {
+    /// A single llama.cpp run is killed after this long
+    pub inference_secs: u64,
+    /// Connecting to the ASR backend
+    pub asr_connect_secs: u64,
+    /// A whole ASR request, including upload and transcription
+    pub asr_secs: u64,
+}
+
+impl Default for TimeoutsSection {
+    fn default() -> Self {
+        Self { inference_secs: 120, asr_connect_secs: 10, asr_secs: 120 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheSection {
+    pub enabled: bool,
+    /// Translations kept in memory; the least recently used are evicted first
+    pub capacity: usize,
+    /// JSON-lines file that keeps the cache across restarts; memory only when unset
+    pub path: Option<String>,
+}
+
+impl Default for CacheSection {
+    fn default() -> Self {
+        Self { enabled: true, capacity: 1000, path: None }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MemorySection {
+    /// TMX file loaded at startup
+    pub path: Option<String>,
+    /// Minimum word-level similarity (0.0 - 1.0) for a fuzzy match to reach the prompt
+    pub threshold: f32,
+    /// Fuzzy matches given to the model per request
+    pub max_examples: usize,
+}
+
+impl Default for MemorySection {
+    fn default() -> Self {
+        Self { path: None, threshold: 0.75, max_examples: 3 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProtectSection {
+    /// Keep placeholders, format specifiers, URLs, emails, code, markup tags and
+    /// `<dnt>...</dnt>` spans out of the model's reach
+    pub enabled: bool,
+    /// Extra runs when the model drops a protected span, before the request fails
+    pub retries: u32,
+}
+
+impl Default for ProtectSection {
+    fn default() -> Self {
+        Self { enabled: true, retries: 1 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ChunkingSection {
+    /// Estimated source tokens per chunk of a long input. Derived from `max_tokens` and
+    /// `n_ctx` when unset; a smaller value only lowers that.
+    pub chunk_tokens: Option<usize>,
+    /// Preceding sentences passed to the model as context for each chunk
+    pub context_sentences: usize,
+}
+
+impl Default for ChunkingSection {
+    fn default() -> Self {
+        Self { chunk_tokens: None, context_sentences: 2 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ModelsSection {
+    /// Directory scanned for `*.gguf` files, each registered under its file name
+    /// without the extension
+    pub dir: String,
+    /// Extra names for model files, e.g. `gemma-9b = "/data/gemma-2-9b-it.Q4_K_M.gguf"`,
+    /// or a table that also sets the model's template and context size; these win over
+    /// scanned files of the same name
+    pub named: BTreeMap<String, ModelEntry>,
+}
+
+/// A `[models.named]` entry: just the file, or the file with its own settings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ModelEntry {
+    Path(String),
+    Settings(ModelSettings),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ModelSettings {
+    pub path: String,
+    /// Chat template family for this model; detected from the file when unset
+    pub template: Option<String>,
+    pub template_file: Option<String>,
+    pub n_ctx: Option<usize>,
+}
+
+impl ModelEntry {
+    pub fn settings(&self) -> ModelSettings {
+        match self {
+            ModelEntry::Path(path) => ModelSettings { path: path.clone(), ..ModelSettings::default() },
+            ModelEntry::Settings(settings) => settings.clone(),
+        }
+    }
+}
+
+impl ModelSettings {
+    /// Point `gemma` at this model. Its template is its own, detected unless set, rather
+    /// than the one configured for `gemma.model_path`.
+    pub fn apply(&self, gemma: &mut GemmaSection) {
+        gemma.model_path = self.path.clone();
+        gemma.template = self.template.clone().unwrap_or_else(|| "auto".to_string());
+        gemma.template_file = self.template_file.clone();
+        if let Some(n_ctx) = self.n_ctx {
+            gemma.n_ctx = n_ctx;
+        }
+    }
+}
+
+impl Default for ModelsSection {
+    fn default() -> Self {
+        Self { dir: "models".to_string(), named: BTreeMap::new() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LanguageSection {
+    /// Direction used when none is given on the command line or in a request
+    pub default_direction: String,
+    /// Enabled language pairs, e.g. `["es-en", "en-es"]`
+    pub pairs: Vec<String>,
+}
+
+impl Default for LanguageSection {
+    fn default() -> Self {
+        Self {
+            default_direction: "es-en".to_string(),
+            pairs: vec!["es-en".to_string(), "en-es".to_string()],
+        }
+    }
+}
+
+impl LanguageSection {
+    pub fn is_enabled(&self, direction: &str) -> bool {
+        self.pairs.iter().any(|p| p == direction)
+    }
+}
+
+impl Config {
+    /// Load the file layer.
+    ///
+    /// An explicit `--config` path must exist and is used on its own. Otherwise the
+    /// XDG file and then `./gemma-translator.toml` are merged, the local file winning.
+    pub fn load(explicit: Option<&str>) -> Result<Self> {
+        Self::load_from(explicit, &discovered_paths())
+    }
+
+    fn load_from(explicit: Option<&str>, discovered: &[PathBuf]) -> Result<Self> {
+        let mut merged = toml::Table::new();
+
+        if let Some(path) = explicit {
+            merge_tables(&mut merged, read_table(Path::new(path))?);
+            log::info!("Loaded config from {}", path);
+        } else {
+            for path in discovered {
+                if path.is_file() {
+                    merge_tables(&mut merged, read_table(path)?);
+                    log::info!("Loaded config from {}", path.display());
+                }
+            }
+        }
+
+        let cfg: Config = toml::Value::Table(merged)
+            .try_into()
+            .map_err(|e| anyhow!("Invalid configuration: {}", e))?;
+        Ok(cfg)
+    }
+
+    /// Sanity checks that only make sense once every layer has been applied.
+    pub fn validate(&self) -> Result<()> {
+        for pair in &self.languages.pairs {
+            if crate::gemma::Direction::from_str(pair).is_none() {
+                return Err(anyhow!("Unsupported language pair in config: {}", pair));
+            }
+        }
+        if !self.languages.is_enabled(&self.languages.default_direction) {
+            return Err(anyhow!(
+                "Default direction {} is not one of the enabled pairs {:?}",
+                self.languages.default_direction,
+                self.languages.pairs
+            ));
+        }
+        if self.server.workers == 0 {
+            return Err(anyhow!("server.workers must be greater than zero"));
+        }
+        if self.gemma.n_ctx == 0 {
+            return Err(anyhow!("gemma.n_ctx must be greater than zero"));
+        }
+        for (name, entry) in &self.models.named {
+            let settings = entry.settings();
+            if settings.n_ctx == Some(0) {
+                return Err(anyhow!("models.named.{}.n_ctx must be greater than zero", name));
+            }
+            if let Some(template) = settings.template.filter(|t| t != "auto" && crate::template::Family::from_str(t).is_none()) {
+                return Err(anyhow!(
+                    "Unknown template {} for models.named.{}; use auto, gemma, llama3, chatml or mistral",
+                    template,
+                    name
+                ));
+            }
+        }
+        if self.gemma.template != "auto" && crate::template::Family::from_str(&self.gemma.template).is_none() {
+            return Err(anyhow!(
+                "Unknown gemma.template {}; use auto, gemma, llama3, chatml or mistral",
+                self.gemma.template
+            ));
+        }
+        self.gemma_config().validate_sampling().map_err(|e| anyhow!("sampling.{}", e))?;
+        if self.asr.max_upload_mb == 0 || self.asr.chunk_concurrency == 0 {
+            return Err(anyhow!("asr.max_upload_mb and asr.chunk_concurrency must be greater than zero"));
+        }
+        if self.cache.enabled && self.cache.capacity == 0 {
+            return Err(anyhow!("cache.capacity must be greater than zero; set cache.enabled = false to turn the cache off"));
+        }
+        for pair in self.glossaries.keys() {
+            if !self.languages.is_enabled(pair) {
+                return Err(anyhow!("Glossary configured for {}, which is not one of the enabled pairs {:?}", pair, self.languages.pairs));
+            }
+        }
+        if !(self.memory.threshold > 0.0 && self.memory.threshold <= 1.0) {
+            return Err(anyhow!("memory.threshold must be between 0 (exclusive) and 1"));
+        }
+        let t = &self.timeouts;
+        if t.inference_secs == 0 || t.asr_connect_secs == 0 || t.asr_secs == 0 {
+            return Err(anyhow!("timeouts must be greater than zero"));
+        }
+        Ok(())
+    }
+
+    pub fn gemma_config(&self) -> crate::gemma::GemmaConfig {
+        crate::gemma::GemmaConfig {
+            model_path: self.gemma.model_path.clone(),
+            n_ctx: self.gemma.n_ctx,
+            max_tokens: self.sampling.max_tokens,
+            temperature: self.sampling.temperature,
+            top_k: self.sampling.top_k,
+            top_p: self.sampling.top_p,
+            min_p: self.sampling.min_p,
+            repeat_penalty: self.sampling.repeat_penalty,
+            seed: self.sampling.seed,
+            stop: self.sampling.stop.clone(),
+            phrasebook_fallback: self.gemma.phrasebook_fallback,
+            timeout: Duration::from_secs(self.timeouts.inference_secs),
+            protect: self.protect.enabled,
+            protect_retries: self.protect.retries,
+            chunk_tokens: self.chunking.chunk_tokens,
+            context_sentences: self.chunking.context_sentences,
+            template: self.gemma.template.clone(),
+            template_file: self.gemma.template_file.clone(),
+            server: None,
+        }
+    }
+
+    #[cfg(feature = "asr")]
+    pub fn asr_config(&self) -> crate::asr::AsrConfig {
+        crate::asr::AsrConfig {
+            api_key: self.asr.api_key.clone(),
+            use_local: self.asr.backend == AsrBackend::Local,
+            local_url: self.asr.url.clone(),
+            connect_timeout: Duration::from_secs(self.timeouts.asr_connect_secs),
+            timeout: Duration::from_secs(self.timeouts.asr_secs),
+            retry: crate::asr::RetryPolicy {
+                max_retries: self.asr.max_retries,
+                base_delay: Duration::from_millis(self.asr.retry_base_ms),
+                max_delay: Duration::from_millis(self.asr.retry_max_ms),
+            },
+            failover_local: self.asr.failover_local,
+            max_upload_bytes: self.asr.max_upload_mb * 1024 * 1024,
+            chunk_concurrency: self.asr.chunk_concurrency,
+        }
+    }
+}
+
+fn discovered_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    let xdg = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")));
+    if let Some(dir) = xdg {
+        paths.push(dir.join("gemma-translator").join("config.toml"));
+    }
+    paths.push(PathBuf::from(LOCAL_CONFIG_FILE));
+    paths
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read config {}: {}", path.display(), e))?;
+    text.parse::<toml::Table>()
+        .map_err(|e| anyhow!("Failed to parse config {}: {}", path.display(), e))
+}
+
+// Recursively overlay `top` onto `base`, so a later file only needs the keys it changes.
+fn merge_tables(base: &mut toml::Table, top: toml::Table) {
+    for (key, value) in top {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(t)) => merge_tables(b, t),
+            (_, v) => {
+                base.insert(key, v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn scratch(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("config-test-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn merges_nested_tables_key_by_key() {
+        let mut base: toml::Table = "[sampling]\ntemperature = 0.3\nmax_tokens = 100\n[server]\nport = 9000\n".parse().unwrap();
+        let top: toml::Table = "[sampling]\ntemperature = 0.4\nstop = [\"x\"]\n[languages]\npairs = [\"es-en\"]\n".parse().unwrap();
+        merge_tables(&mut base, top);
+        let expected: toml::Table = "[sampling]\ntemperature = 0.4\nmax_tokens = 100\nstop = [\"x\"]\n[server]\nport = 9000\n[languages]\npairs = [\"es-en\"]\n"
+            .parse()
+            .unwrap();
+        assert_eq!(base, expected);
+    }
+
+    // The only test touching the environment, so no other test sees these variables
+    #[test]
+    fn layers_cli_over_env_over_files_over_defaults() {
+        let xdg = std::env::temp_dir().join(format!("config-test-{}-xdg", std::process::id()));
+        std::env::set_var("XDG_CONFIG_HOME", &xdg);
+        let discovered = discovered_paths();
+        assert_eq!(discovered, [xdg.join("gemma-translator").join("config.toml"), PathBuf::from(LOCAL_CONFIG_FILE)]);
+
+        let xdg_file = scratch(
+            "xdg/gemma-translator/config.toml",
+            "[gemma]\nn_ctx = 1024\n[sampling]\ntemperature = 0.3\nmax_tokens = 100\ntop_k = 20\n[server]\nport = 9000\nbind = \"127.0.0.1\"\n",
+        );
+        let local_file = scratch("local.toml", "[sampling]\ntemperature = 0.4\nmax_tokens = 200\n[server]\nport = 9100\n");
+        let missing = std::env::temp_dir().join(format!("config-test-{}-missing.toml", std::process::id()));
+        let files = Config::load_from(None, &[xdg_file, local_file.clone(), missing.clone()]).unwrap();
+
+        std::env::set_var("GEMMA_TRANSLATOR_TEMPERATURE", "0.5");
+        std::env::set_var("GEMMA_TRANSLATOR_MAX_TOKENS", "300");
+        let args = crate::Args::try_parse_from(["gemma-edge-translator", "--temperature", "0.6"]);
+        std::env::remove_var("GEMMA_TRANSLATOR_TEMPERATURE");
+        std::env::remove_var("GEMMA_TRANSLATOR_MAX_TOKENS");
+        std::env::remove_var("XDG_CONFIG_HOME");
+        let cfg = crate::apply_args(files, &args.unwrap()).unwrap();
+
+        assert_eq!(cfg.sampling.temperature, 0.6, "CLI over env");
+        assert_eq!(cfg.sampling.max_tokens, 300, "env over files");
+        assert_eq!(cfg.server.port, 9100, "local file over XDG file");
+        assert_eq!(cfg.server.bind, "127.0.0.1", "XDG file over defaults");
+        assert_eq!((cfg.gemma.n_ctx, cfg.sampling.top_k), (1024, Some(20)));
+        assert_eq!(cfg.server.workers, ServerSection::default().workers, "defaults");
+        assert_eq!(cfg.languages.default_direction, LanguageSection::default().default_direction);
+
+        // An explicit file is used on its own and must exist
+        let explicit = Config::load_from(local_file.to_str(), &discovered).unwrap();
+        assert_eq!((explicit.server.port, explicit.gemma.n_ctx), (9100, GemmaSection::default().n_ctx));
+        let err = Config::load_from(missing.to_str(), &[]).unwrap_err().to_string();
+        assert!(err.starts_with("Failed to read config"), "{}", err);
+
+        std::fs::remove_dir_all(&xdg).unwrap();
+        std::fs::remove_file(&local_file).unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_inconsistent_settings() {
+        assert!(Config::default().validate().is_ok());
+        let cases = [
+            ("[languages]\npairs = [\"es-fr\"]", "Unsupported language pair in config: es-fr"),
+            ("[languages]\npairs = [\"es-en\"]\ndefault_direction = \"en-es\"", "Default direction en-es is not one of"),
+            ("[server]\nworkers = 0", "server.workers must be greater than zero"),
+            ("[gemma]\nn_ctx = 0", "gemma.n_ctx must be greater than zero"),
+            ("[gemma]\ntemplate = \"alpaca\"", "Unknown gemma.template alpaca"),
+            ("[models.named]\nsmall = { path = \"s.gguf\", n_ctx = 0 }", "models.named.small.n_ctx must be greater than zero"),
+            ("[models.named]\nsmall = { path = \"s.gguf\", template = \"alpaca\" }", "Unknown template alpaca for models.named.small"),
+            ("[sampling]\ntemperature = 3.0", "sampling.temperature must be between 0 and 2"),
+            ("[sampling]\nmax_tokens = 0", "sampling.max_tokens must be greater than zero"),
+            ("[asr]\nchunk_concurrency = 0", "asr.max_upload_mb and asr.chunk_concurrency must be greater than zero"),
+            ("[cache]\ncapacity = 0", "cache.capacity must be greater than zero"),
+            ("[languages]\npairs = [\"es-en\"]\n[glossaries]\nen-es = \"terms.csv\"", "Glossary configured for en-es"),
+            ("[memory]\nthreshold = 0.0", "memory.threshold must be between 0 (exclusive) and 1"),
+            ("[timeouts]\nasr_secs = 0", "timeouts must be greater than zero"),
+        ];
+        for (text, expected) in cases {
+            let cfg: Config = toml::from_str(text).unwrap();
+            let err = cfg.validate().unwrap_err().to_string();
+            assert!(err.starts_with(expected), "{:?}: {}", text, err);
+        }
+        // Turning the cache off makes its capacity irrelevant
+        let cfg: Config = toml::from_str("[cache]\nenabled = false\ncapacity = 0").unwrap();
+        assert!(cfg.validate().is_ok());
+    }
+}
@@ -0,0 +1,223 @@
+#[cfg(feature = "asr")]
+use crate::asr::AsrError;
+use crate::gemma::TranslateError;
+use serde::{Serialize, Serializer};
+
+/// Stable, machine-readable error codes. HTTP clients get them in the `code` field of
+/// error bodies and the CLI exits with the matching status, so the names must not change.
+#[cfg_attr(not(feature = "ui"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidRequest,
+    Forbidden,
+    NotFound,
+    Config,
+    AudioFormat,
+    AudioTooLarge,
+    NoSpeech,
+    AsrAuth,
+    AsrUnreachable,
+    AsrFailed,
+    AsrTimeout,
+    ModelNotFound,
+    ModelUnsupported,
+    InferenceUnavailable,
+    InferenceFailed,
+    InferenceTimeout,
+    PlaceholderLost,
+    QueueFull,
+    Cancelled,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::Config => "CONFIG",
+            ErrorCode::AudioFormat => "AUDIO_FORMAT",
+            ErrorCode::AudioTooLarge => "AUDIO_TOO_LARGE",
+            ErrorCode::NoSpeech => "NO_SPEECH",
+            ErrorCode::AsrAuth => "ASR_AUTH",
+            ErrorCode::AsrUnreachable => "ASR_UNREACHABLE",
+            ErrorCode::AsrFailed => "ASR_FAILED",
+            ErrorCode::AsrTimeout => "ASR_TIMEOUT",
+            ErrorCode::ModelNotFound => "MODEL_NOT_FOUND",
+            ErrorCode::ModelUnsupported => "MODEL_UNSUPPORTED",
+            ErrorCode::InferenceUnavailable => "INFERENCE_UNAVAILABLE",
+            ErrorCode::InferenceFailed => "INFERENCE_FAILED",
+            ErrorCode::InferenceTimeout => "INFERENCE_TIMEOUT",
+            ErrorCode::PlaceholderLost => "PLACEHOLDER_LOST",
+            ErrorCode::QueueFull => "QUEUE_FULL",
+            ErrorCode::Cancelled => "CANCELLED",
+            ErrorCode::Internal => "INTERNAL",
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    pub fn http_status(&self) -> u16 {
+        match self {
+            ErrorCode::InvalidRequest | ErrorCode::AudioFormat => 400,
+            ErrorCode::AsrAuth => 401,
+            ErrorCode::Forbidden => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::AudioTooLarge => 413,
+            ErrorCode::NoSpeech | ErrorCode::ModelUnsupported => 422,
+            // nginx's "client closed request"; only logs ever see it
+            ErrorCode::Cancelled => 499,
+            ErrorCode::AsrFailed => 502,
+            ErrorCode::AsrUnreachable
+            | ErrorCode::ModelNotFound
+            | ErrorCode::InferenceUnavailable
+            | ErrorCode::QueueFull => 503,
+            ErrorCode::AsrTimeout | ErrorCode::InferenceTimeout => 504,
+            ErrorCode::Config | ErrorCode::InferenceFailed | ErrorCode::PlaceholderLost | ErrorCode::Internal => 500,
+        }
+    }
+
+    /// Process exit status, following the BSD `sysexits.h` conventions.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorCode::InvalidRequest => 64,
+            ErrorCode::AudioFormat | ErrorCode::AudioTooLarge | ErrorCode::NoSpeech | ErrorCode::ModelUnsupported => 65,
+            ErrorCode::NotFound | ErrorCode::ModelNotFound => 66,
+            ErrorCode::AsrUnreachable | ErrorCode::InferenceUnavailable => 69,
+            ErrorCode::InferenceFailed | ErrorCode::PlaceholderLost => 70,
+            ErrorCode::QueueFull | ErrorCode::AsrTimeout | ErrorCode::InferenceTimeout => 75,
+            ErrorCode::AsrFailed => 76,
+            ErrorCode::AsrAuth | ErrorCode::Forbidden => 77,
+            ErrorCode::Config => 78,
+            // Conventional status for a process stopped by SIGINT
+            ErrorCode::Cancelled => 130,
+            ErrorCode::Internal => 1,
+        }
+    }
+
+    /// Code for an error that has already been turned into an `anyhow::Error`.
+    pub fn of(err: &anyhow::Error) -> Self {
+        if let Some(e) = err.downcast_ref::<Error>() {
+            return e.code();
+        }
+        if let Some(e) = err.downcast_ref::<TranslateError>() {
+            return e.code();
+        }
+        #[cfg(feature = "asr")]
+        if let Some(e) = err.downcast_ref::<AsrError>() {
+            return e.code();
+        }
+        ErrorCode::Internal
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Crate-level error for everything that crosses the HTTP or CLI boundary.
+#[cfg_attr(not(feature = "ui"), allow(dead_code))]
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    QueueFull(String),
+    #[error("{0}")]
+    ModelUnsupported(String),
+    #[cfg(feature = "asr")]
+    #[error(transparent)]
+    Asr(#[from] AsrError),
+    #[error(transparent)]
+    Translate(#[from] TranslateError),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            Error::Forbidden(_) => ErrorCode::Forbidden,
+            Error::NotFound(_) => ErrorCode::NotFound,
+            Error::QueueFull(_) => ErrorCode::QueueFull,
+            Error::ModelUnsupported(_) => ErrorCode::ModelUnsupported,
+            #[cfg(feature = "asr")]
+            Error::Asr(e) => e.code(),
+            Error::Translate(e) => e.code(),
+            Error::Internal(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+impl TranslateError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            TranslateError::ModelMissing(_) => ErrorCode::ModelNotFound,
+            TranslateError::BackendUnavailable => ErrorCode::InferenceUnavailable,
+            TranslateError::ProcessFailed { .. } | TranslateError::EmptyOutput => ErrorCode::InferenceFailed,
+            TranslateError::Timeout(_) => ErrorCode::InferenceTimeout,
+            TranslateError::Cancelled => ErrorCode::Cancelled,
+            TranslateError::PlaceholdersLost(_) => ErrorCode::PlaceholderLost,
+            TranslateError::Template(_) => ErrorCode::Config,
+        }
+    }
+}
+
+#[cfg(feature = "asr")]
+impl AsrError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AsrError::MissingApiKey | AsrError::Unauthorized(_) => ErrorCode::AsrAuth,
+            AsrError::Unreachable(_) => ErrorCode::AsrUnreachable,
+            AsrError::AudioFormat(_) => ErrorCode::AudioFormat,
+            AsrError::TooLarge(_) => ErrorCode::AudioTooLarge,
+            AsrError::NoSpeech => ErrorCode::NoSpeech,
+            AsrError::Backend(_) => ErrorCode::AsrFailed,
+            AsrError::Timeout(_) => ErrorCode::AsrTimeout,
+            AsrError::Cancelled => ErrorCode::Cancelled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[cfg(feature = "ui")]
+    #[test]
+    fn errors_map_to_stable_codes_and_statuses() {
+        let cases: [(Error, &str, u16, i32); 7] = [
+            (AsrError::Unauthorized("bad key".into()).into(), "ASR_AUTH", 401, 77),
+            (AsrError::MissingApiKey.into(), "ASR_AUTH", 401, 77),
+            (AsrError::AudioFormat("not a WAV".into()).into(), "AUDIO_FORMAT", 400, 65),
+            (AsrError::TooLarge("30 MB".into()).into(), "AUDIO_TOO_LARGE", 413, 65),
+            (TranslateError::ModelMissing("m.gguf".into()).into(), "MODEL_NOT_FOUND", 503, 66),
+            (TranslateError::Timeout(Duration::from_secs(5)).into(), "INFERENCE_TIMEOUT", 504, 75),
+            (Error::QueueFull("full".into()), "QUEUE_FULL", 503, 75),
+        ];
+        for (err, code, status, exit) in cases {
+            let c = err.code();
+            assert_eq!((c.as_str(), c.http_status(), c.exit_code()), (code, status, exit), "{}", err);
+            assert_eq!(serde_json::to_string(&c).unwrap(), format!("\"{}\"", code));
+        }
+    }
+
+    #[test]
+    fn recovers_the_code_through_anyhow() {
+        let err = anyhow::Error::new(TranslateError::Timeout(Duration::from_secs(5))).context("translating line 3");
+        assert_eq!(ErrorCode::of(&err), ErrorCode::InferenceTimeout);
+        let err: anyhow::Error = Error::NotFound("no such job".into()).into();
+        assert_eq!(ErrorCode::of(&err), ErrorCode::NotFound);
+        #[cfg(feature = "asr")]
+        assert_eq!(ErrorCode::of(&AsrError::TooLarge("30 MB".into()).into()), ErrorCode::AudioTooLarge);
+        assert_eq!(ErrorCode::of(&anyhow::anyhow!("something else")), ErrorCode::Internal);
+    }
+}
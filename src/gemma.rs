@@ -1,12 +1,161 @@
-use anyhow::{anyhow, Result};
+use crate::cache;
+use crate::cancel::CancelToken;
+use crate::cleanup;
+use crate::glossary::{self, Glossary, GlossaryEntry};
+use crate::llama_server::LlamaServer;
+use crate::memory;
+use crate::metrics;
+use crate::protect;
+use crate::segment;
+use crate::template::{self, Template};
+use std::io::Read;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+#[derive(Clone, Debug)]
 pub struct GemmaConfig {
     pub model_path: String,
     pub n_ctx: usize,
+    pub max_tokens: usize,
+    pub temperature: f32,
+    /// Sample from the k most likely tokens (0 disables)
+    pub top_k: Option<u32>,
+    /// Nucleus sampling threshold
+    pub top_p: Option<f32>,
+    /// Drop tokens less likely than this fraction of the most likely one
+    pub min_p: Option<f32>,
+    pub repeat_penalty: Option<f32>,
+    /// Fixed RNG seed for reproducible output
+    pub seed: Option<u32>,
+    /// Generation ends at the first of these, which is left out of the output
+    pub stop: Vec<String>,
+    /// Answer known phrases from the built-in phrasebook when inference fails
+    pub phrasebook_fallback: bool,
+    /// A llama.cpp run is killed once it exceeds this
+    pub timeout: Duration,
+    /// Mask placeholders, URLs, markup and `<dnt>` spans before prompting
+    pub protect: bool,
+    /// Extra runs when the model drops a protected span
+    pub protect_retries: u32,
+    /// Upper bound on the estimated source tokens per chunk of a long input
+    pub chunk_tokens: Option<usize>,
+    /// Preceding sentences given to the model as context for each chunk
+    pub context_sentences: usize,
+    /// Prompt template family (`auto` detects it from the model file)
+    pub template: String,
+    /// TOML prompt template used instead of the built-in ones
+    pub template_file: Option<String>,
+    /// llama.cpp server with the model already loaded; when set, runs go to it instead
+    /// of a new llama.cpp process
+    pub server: Option<Arc<LlamaServer>>,
 }
 
+/// Per-request changes to the configured sampling settings; unset fields keep theirs.
+#[cfg(feature = "ui")]
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SamplingOverrides {
+    max_tokens: Option<usize>,
+    temperature: Option<f32>,
+    top_k: Option<u32>,
+    top_p: Option<f32>,
+    min_p: Option<f32>,
+    repeat_penalty: Option<f32>,
+    seed: Option<u32>,
+    stop: Option<Vec<String>>,
+}
+
+#[cfg(feature = "ui")]
+impl SamplingOverrides {
+    /// `cfg` with these overrides applied, or why the result is invalid.
+    pub fn apply(&self, cfg: &GemmaConfig) -> Result<GemmaConfig, String> {
+        let mut cfg = cfg.clone();
+        cfg.max_tokens = self.max_tokens.unwrap_or(cfg.max_tokens);
+        cfg.temperature = self.temperature.unwrap_or(cfg.temperature);
+        cfg.top_k = self.top_k.or(cfg.top_k);
+        cfg.top_p = self.top_p.or(cfg.top_p);
+        cfg.min_p = self.min_p.or(cfg.min_p);
+        cfg.repeat_penalty = self.repeat_penalty.or(cfg.repeat_penalty);
+        cfg.seed = self.seed.or(cfg.seed);
+        if let Some(stop) = &self.stop {
+            cfg.stop = stop.clone();
+        }
+        cfg.validate_sampling()?;
+        Ok(cfg)
+    }
+}
+
+// Tokens taken by the chat template around the system prompt and input
+const TEMPLATE_TOKENS: usize = 32;
+
+impl GemmaConfig {
+    /// Settings that change the generated text, for cache keys. Masking is part of it:
+    /// the same input translates differently with its spans protected or not.
+    pub fn sampling_key(&self) -> String {
+        format!(
+            "temperature={};max_tokens={};top_k={:?};top_p={:?};min_p={:?};repeat_penalty={:?};seed={:?};stop={:?};template={};template_file={:?};protect={};protect_retries={}",
+            self.temperature,
+            self.max_tokens,
+            self.top_k,
+            self.top_p,
+            self.min_p,
+            self.repeat_penalty,
+            self.seed,
+            self.stop,
+            self.template,
+            self.template_file,
+            self.protect,
+            self.protect_retries
+        )
+    }
+
+    /// Check the sampling settings, wherever they came from.
+    pub fn validate_sampling(&self) -> Result<(), String> {
+        if self.max_tokens == 0 {
+            return Err("max_tokens must be greater than zero".to_string());
+        }
+        if !(0.0..=2.0).contains(&self.temperature) {
+            return Err("temperature must be between 0 and 2".to_string());
+        }
+        if self.top_p.is_some_and(|p| !(p > 0.0 && p <= 1.0)) {
+            return Err("top_p must be between 0 (exclusive) and 1".to_string());
+        }
+        if self.min_p.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+            return Err("min_p must be between 0 and 1".to_string());
+        }
+        if self.repeat_penalty.is_some_and(|p| p <= 0.0) {
+            return Err("repeat_penalty must be greater than zero".to_string());
+        }
+        if self.stop.iter().any(|s| s.is_empty()) {
+            return Err("stop sequences must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// Estimated source tokens per chunk. A translation can run half again as long as
+    /// its source, so the output has to fit `max_tokens`; the source may use half of the
+    /// context left after the output, the rest being for instructions and context.
+    pub fn chunk_budget(&self) -> usize {
+        let by_output = self.max_tokens * 2 / 3;
+        let by_context = self.n_ctx.saturating_sub(self.max_tokens) / 2;
+        self.chunk_tokens
+            .unwrap_or(usize::MAX)
+            .min(by_output)
+            .min(by_context)
+            .max(segment::MIN_CHUNK_TOKENS)
+    }
+
+    /// Chat template for the configured model, detected from its metadata unless set.
+    pub fn prompt_template(&self) -> Result<Arc<Template>, TranslateError> {
+        template::resolve(&self.model_path, &self.template, self.template_file.as_deref())
+            .map_err(|e| TranslateError::Template(e.to_string()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Direction {
     EnToEs,
     EsToEn,
@@ -20,19 +169,188 @@ impl Direction {
             _ => None,
         }
     }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::EnToEs => "en-es",
+            Direction::EsToEn => "es-en",
+        }
+    }
+
+    /// Source and target language codes.
+    pub fn languages(&self) -> (&'static str, &'static str) {
+        match self {
+            Direction::EnToEs => ("en", "es"),
+            Direction::EsToEn => ("es", "en"),
+        }
+    }
+}
+
+/// Why a translation could not be produced.
+#[derive(Debug, thiserror::Error)]
+pub enum TranslateError {
+    #[error("Gemma model not found at: {0}. Please download the model first.")]
+    ModelMissing(String),
+    #[error("No working llama.cpp executable found (tried llama, llama-cli, main, ./llama.cpp/main)")]
+    BackendUnavailable,
+    #[error("llama.cpp ({exe}) failed: {reason}")]
+    ProcessFailed { exe: String, reason: String },
+    #[error("llama.cpp produced no output")]
+    EmptyOutput,
+    #[error("Inference timed out after {}s", .0.as_secs())]
+    Timeout(Duration),
+    #[error("Translation cancelled")]
+    Cancelled,
+    #[error("Translation lost protected text: {}", .0.join(", "))]
+    PlaceholdersLost(Vec<String>),
+    #[error("{0}")]
+    Template(String),
+}
+
+/// Text produced for a request, and whether it came from the phrasebook fallback
+/// rather than the model.
+#[derive(Debug, Clone)]
+pub struct Translation {
+    pub text: String,
+    pub fallback: bool,
+    /// Glossary terms whose required translation the model left out
+    pub violations: Vec<GlossaryEntry>,
+}
+
+/// Translate `input`. Setting `cancel` kills the running llama.cpp process.
+#[cfg(feature = "realtime")]
+pub fn translate(
+    cfg: &GemmaConfig,
+    dir: Direction,
+    input: &str,
+    cancel: &CancelToken,
+) -> Result<Translation, TranslateError> {
+    translate_with_glossary(cfg, dir, input, None, cancel)
+}
+
+/// Translate with `glossary` on top of the glossary configured for the language pair.
+pub fn translate_with_glossary(
+    cfg: &GemmaConfig,
+    dir: Direction,
+    input: &str,
+    glossary: Option<&Glossary>,
+    cancel: &CancelToken,
+) -> Result<Translation, TranslateError> {
+    translate_streaming(cfg, dir, input, glossary, cancel, &mut |_| {})
+}
+
+/// Like [`translate_with_glossary`], but hands each chunk of generated text to
+/// `on_token` as llama.cpp produces it. Returns the full translation.
+///
+/// Input too long for one run is split into sentences and packed into chunks that fit
+/// the token budget. Each chunk is translated with the sentences before it as context,
+/// and the results are joined with the whitespace that separated the chunks.
+pub fn translate_streaming(
+    cfg: &GemmaConfig,
+    dir: Direction,
+    input: &str,
+    glossary: Option<&Glossary>,
+    cancel: &CancelToken,
+    on_token: &mut dyn FnMut(&str),
+) -> Result<Translation, TranslateError> {
+    translate_chunks(cfg, dir, input, glossary, cancel, on_token, &mut |_, _| {})
+}
+
+/// Translate a whole document, calling `on_progress` with the number of chunks done and
+/// the total after each one.
+#[cfg(feature = "ui")]
+pub fn translate_with_progress(
+    cfg: &GemmaConfig,
+    dir: Direction,
+    input: &str,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(usize, usize),
+) -> Result<Translation, TranslateError> {
+    translate_chunks(cfg, dir, input, None, cancel, &mut |_| {}, on_progress)
 }
 
-pub fn translate(cfg: &GemmaConfig, dir: Direction, input: &str) -> Result<String> {
-    if input.trim().is_empty() {
-        return Ok(String::new());
+fn translate_chunks(
+    cfg: &GemmaConfig,
+    dir: Direction,
+    input: &str,
+    glossary: Option<&Glossary>,
+    cancel: &CancelToken,
+    on_token: &mut dyn FnMut(&str),
+    on_chunk: &mut dyn FnMut(usize, usize),
+) -> Result<Translation, TranslateError> {
+    let input = input.trim();
+    if input.is_empty() {
+        on_chunk(1, 1);
+        return Ok(Translation { text: String::new(), fallback: false, violations: Vec::new() });
+    }
+    
+    // Segment with protected spans masked, so a URL or code block is never cut in two
+    let masked = if cfg.protect { protect::mask(input) } else { protect::Masked::unprotected(input) };
+    let budget = cfg.chunk_budget();
+    let sentences = segment::sentences(&masked.text, dir.languages().0);
+    let sentences = segment::fit(&masked.text, sentences, budget);
+    let chunks = segment::pack(&masked.text, &sentences, budget);
+    if chunks.len() <= 1 {
+        let translation = translate_chunk(cfg, dir, input, glossary, &[], cancel, on_token)?;
+        on_chunk(1, 1);
+        return Ok(translation);
+    }
+    
+    log::info!("Translating {} sentences in {} chunks", sentences.len(), chunks.len());
+    let source = |range: &std::ops::Range<usize>| masked.reveal(&masked.text[range.clone()]);
+    let mut result = Translation { text: String::new(), fallback: false, violations: Vec::new() };
+    for (i, chunk) in chunks.iter().enumerate() {
+        if cancel.is_cancelled() {
+            return Err(TranslateError::Cancelled);
+        }
+        if i > 0 {
+            let gap = &masked.text[sentences[chunk.start - 1].end..sentences[chunk.start].start];
+            result.text.push_str(gap);
+            on_token(gap);
+        }
+        let preceding: Vec<String> = sentences[chunk.start.saturating_sub(cfg.context_sentences)..chunk.start]
+            .iter()
+            .map(source)
+            .collect();
+        let text = source(&(sentences[chunk.start].start..sentences[chunk.end - 1].end));
+        let translation = translate_chunk(cfg, dir, &text, glossary, &preceding, cancel, on_token)?;
+        result.text.push_str(&translation.text);
+        result.fallback |= translation.fallback;
+        result.violations.extend(translation.violations);
+        on_chunk(i + 1, chunks.len());
     }
+    Ok(result)
+}
+
+// One run of the model, after the translation memory and the cache have been checked.
+// `preceding` is source text before `input`, passed along as context.
+fn translate_chunk(
+    cfg: &GemmaConfig,
+    dir: Direction,
+    input: &str,
+    glossary: Option<&Glossary>,
+    preceding: &[String],
+    cancel: &CancelToken,
+    on_token: &mut dyn FnMut(&str),
+) -> Result<Translation, TranslateError> {
+    let started = Instant::now();
+    let examples = match memory::lookup(dir, input) {
+        memory::Lookup::Exact(text) => {
+            log::info!("Translation memory hit: {} -> {}", input, text);
+            metrics::observe_translation("memory", started.elapsed().as_secs_f64());
+            on_token(&text);
+            return Ok(Translation { text, fallback: false, violations: Vec::new() });
+        }
+        memory::Lookup::Fuzzy(matches) => matches,
+    };
     
     // Check if model file exists
     if !Path::new(&cfg.model_path).exists() {
-        return Err(anyhow!("Gemma model not found at: {}. Please download the model first.", cfg.model_path));
+        return Err(TranslateError::ModelMissing(cfg.model_path.clone()));
     }
     
-    log::info!("Translating with Gemma: {}", input);
+    let template = cfg.prompt_template()?;
+    let terms = glossary::relevant_terms(dir, glossary, input);
     
     // Create the translation prompt using Gemma format
     let system_prompt = match dir {
@@ -40,27 +358,162 @@ pub fn translate(cfg: &GemmaConfig, dir: Direction, input: &str) -> Result<Strin
         Direction::EnToEs => "You are a professional translator. Translate the following English text to Spanish. Only provide the translation, nothing else.",
     };
     
-    let prompt = format!(
-        "<start_of_turn>system\n{}\n<end_of_turn>\n<start_of_turn>user\n{}\n<end_of_turn>\n<start_of_turn>model\n",
-        system_prompt,
-        input.trim()
+    let mut system_prompt = system_prompt.to_string();
+    if input.contains('\n') {
+        system_prompt.push_str(" Keep the line breaks of the text.");
+    }
+    if !terms.is_empty() {
+        system_prompt.push_str(" Always translate these terms as given:");
+        for term in &terms {
+            system_prompt.push_str(&format!("\n- {} => {}", term.source, term.target));
+        }
+    }
+    if !examples.is_empty() {
+        system_prompt.push_str(" Earlier translations of similar sentences, for reference; reuse their wording where it fits:");
+        for example in &examples {
+            system_prompt.push_str(&format!("\n- {} => {}", example.unit.source, example.unit.target));
+        }
+    }
+    
+    // As many of the preceding sentences as the context window has room for, newest first
+    let mut room = cfg.n_ctx.saturating_sub(
+        cfg.max_tokens + TEMPLATE_TOKENS + segment::estimate_tokens(&system_prompt) + segment::estimate_tokens(input),
     );
+    let keep = preceding
+        .iter()
+        .rev()
+        .take_while(|s| {
+            let tokens = segment::estimate_tokens(s);
+            let fits = tokens <= room;
+            room = room.saturating_sub(tokens);
+            fits
+        })
+        .count();
+    let preceding = &preceding[preceding.len() - keep..];
+    if !preceding.is_empty() {
+        system_prompt.push_str(
+            " The text continues from the passage below, which is translated separately; use it for context only and do not translate it:\n",
+        );
+        system_prompt.push_str(&preceding.join(" "));
+    }
+    
+    // Everything besides the input that shapes the prompt
+    let context: Vec<String> = terms
+        .iter()
+        .map(|t| format!("{}=>{}", t.source, t.target))
+        .chain(examples.iter().map(|m| format!("{}~>{}", m.unit.source, m.unit.target)))
+        .chain(preceding.iter().map(|s| format!("<{}", s)))
+        .collect();
+    let cache_key = cache_key(cfg, dir, input, &context.join("\n"));
+    if let Some(text) = cache_key.as_deref().and_then(cache::lookup) {
+        log::info!("Cache hit: {} -> {}", input, text);
+        metrics::observe_translation("cache", started.elapsed().as_secs_f64());
+        on_token(&text);
+        let violations = check_glossary(&terms, &text);
+        return Ok(Translation { text, fallback: false, violations });
+    }
+    
+    log::info!("Translating with Gemma: {}", input);
+    let masked = if cfg.protect { protect::mask(input) } else { protect::Masked::unprotected(input) };
     
+    if !masked.is_empty() {
+        system_prompt.push_str(&format!(
+            " Markers such as {} stand for text that must stay as it is; copy each marker into the translation exactly once.",
+            protect::marker(0)
+        ));
+    }
+    
+    // Streamed tokens would show the markers, so protected input is only passed on
+    // once restored
+    let mut hold = |_: &str| {};
+    let mut retries_left = cfg.protect_retries;
     // For now, let's try using llama.cpp command line if available
     // This is a fallback approach until we get the Rust API working properly
-    if let Ok(output) = try_llama_cpp_cli(&cfg.model_path, &prompt, cfg.n_ctx) {
-        let result = output.trim().to_string();
-        if !result.is_empty() {
-            log::info!("Translation completed: {} -> {}", input, result);
-            return Ok(result);
+    let error = loop {
+        let prompt = template.render(&system_prompt, masked.text.trim());
+        let stream: &mut dyn FnMut(&str) = if masked.is_empty() { &mut *on_token } else { &mut hold };
+        let output = match run_model(cfg, &template, &prompt, cancel, stream) {
+            Ok(output) => cleanup::clean(&output, masked.text.trim()),
+            Err(e) => break e,
+        };
+        if output.is_empty() {
+            break TranslateError::EmptyOutput;
+        }
+        let result = match masked.restore(&output) {
+            Ok(result) => result,
+            Err(lost) => {
+                let markers: Vec<String> = lost.iter().map(|&i| protect::marker(i)).collect();
+                if retries_left == 0 {
+                    log::warn!("Translation dropped protected spans {}: {}", markers.join(" "), output);
+                    break TranslateError::PlaceholdersLost(lost.iter().map(|&i| masked.spans[i].clone()).collect());
+                }
+                retries_left -= 1;
+                log::warn!("Translation dropped protected spans {}, retrying", markers.join(" "));
+                system_prompt.push_str(&format!(" Keep {} exactly once each.", markers.join(", ")));
+                continue;
+            }
+        };
+        if !masked.is_empty() {
+            on_token(&result);
+        }
+        
+        log::info!("Translation completed: {} -> {}", input, result);
+        metrics::observe_translation("llama_cpp", started.elapsed().as_secs_f64());
+        if let Some(key) = &cache_key {
+            cache::store(key, &result);
         }
+        let violations = check_glossary(&terms, &result);
+        return Ok(Translation { text: result, fallback: false, violations });
+    };
+    
+    if !cfg.phrasebook_fallback || matches!(error, TranslateError::Cancelled) {
+        return Err(error);
     }
     
-    // Fallback to a simple rule-based approach for demo purposes
-    log::warn!("Using fallback translation approach");
+    // The phrasebook only knows a handful of greetings; anything else is still an error
+    log::warn!("Inference failed ({}), trying the phrasebook", error);
+    let Some(result) = phrasebook(dir, input) else {
+        metrics::record_fallback(false);
+        return Err(error);
+    };
+    
+    log::info!("Fallback translation: {} -> {}", input, result);
+    metrics::record_fallback(true);
+    metrics::observe_translation("fallback", started.elapsed().as_secs_f64());
+    on_token(result);
+    Ok(Translation { text: result.to_string(), fallback: true, violations: Vec::new() })
+}
+
+// The prompt asks for the glossary terms but small models don't always comply; report
+// what was missed rather than failing the request
+fn check_glossary(terms: &[GlossaryEntry], output: &str) -> Vec<GlossaryEntry> {
+    let violations = glossary::violations(terms, output);
+    for term in &violations {
+        log::warn!("Glossary term not applied: {} => {} (output: {})", term.source, term.target, output);
+    }
+    violations
+}
+
+// None when caching is off or the model can't be fingerprinted; the request then
+// simply bypasses the cache.
+fn cache_key(cfg: &GemmaConfig, dir: Direction, input: &str, context: &str) -> Option<String> {
+    if !cache::enabled() {
+        return None;
+    }
+    let fingerprint = match cache::model_fingerprint(Path::new(&cfg.model_path)) {
+        Ok(fp) => fp,
+        Err(e) => {
+            log::warn!("Translation cache bypassed: {}", e);
+            return None;
+        }
+    };
+    Some(cache::key(&fingerprint, dir.as_str(), &cfg.sampling_key(), context, input))
+}
+
+// Simple rule-based translations for demo setups without a working llama.cpp
+fn phrasebook(dir: Direction, input: &str) -> Option<&'static str> {
     let result = match dir {
         Direction::EsToEn => {
-            // Simple Spanish to English translations for demo
             match input.to_lowercase().trim() {
                 "hola" => "Hello",
                 "adiós" | "adios" => "Goodbye", 
@@ -72,11 +525,10 @@ pub fn translate(cfg: &GemmaConfig, dir: Direction, input: &str) -> Result<Strin
                 "buenos días" | "buenos dias" => "Good morning",
                 "buenas noches" => "Good night",
                 "¿cómo estás?" | "como estas" => "How are you?",
-                _ => &format!("[Translation] {}", input),
+                _ => return None,
             }
         },
         Direction::EnToEs => {
-            // Simple English to Spanish translations for demo
             match input.to_lowercase().trim() {
                 "hello" | "hi" => "Hola",
                 "goodbye" | "bye" => "Adiós",
@@ -88,50 +540,319 @@ pub fn translate(cfg: &GemmaConfig, dir: Direction, input: &str) -> Result<Strin
                 "good morning" => "Buenos días",
                 "good night" => "Buenas noches",
                 "how are you?" | "how are you" => "¿Cómo estás?",
-                _ => &format!("[Traducción] {}", input),
+                _ => return None,
             }
         }
     };
-    
-    log::info!("Fallback translation: {} -> {}", input, result);
-    Ok(result.to_string())
+    Some(result)
+}
+
+// Common llama.cpp executable names, tried in order
+const LLAMA_EXECUTABLES: [&str; 4] = ["llama", "llama-cli", "main", "./llama.cpp/main"];
+
+/// First llama.cpp executable that exists, either as a path or somewhere on `PATH`.
+#[cfg(feature = "ui")]
+pub fn find_llama_executable() -> Option<std::path::PathBuf> {
+    let path_dirs: Vec<std::path::PathBuf> = std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    LLAMA_EXECUTABLES.iter().find_map(|exe| {
+        if exe.contains('/') {
+            let path = Path::new(exe);
+            return path.is_file().then(|| path.to_path_buf());
+        }
+        path_dirs.iter().map(|dir| dir.join(exe)).find(|path| path.is_file())
+    })
+}
+
+/// Whether `path` is a readable file starting with the GGUF magic.
+#[cfg(feature = "ui")]
+pub fn is_gguf_file(path: &str) -> std::io::Result<bool> {
+    let mut magic = [0u8; 4];
+    let mut file = std::fs::File::open(path)?;
+    Ok(file.read_exact(&mut magic).is_ok() && &magic == b"GGUF")
+}
+
+// Command line for one run. Sampling options left unset are not passed, so llama.cpp's
+// own defaults apply.
+fn llama_args(cfg: &GemmaConfig, prompt: &str) -> Vec<String> {
+    let mut args: Vec<String> = vec![
+        "-m".into(),
+        cfg.model_path.clone(),
+        "-p".into(),
+        prompt.into(),
+        "-c".into(),
+        cfg.n_ctx.to_string(),
+        "-n".into(),
+        cfg.max_tokens.to_string(),
+        "--temp".into(),
+        cfg.temperature.to_string(),
+        "-b".into(),
+        "1".into(),
+        // Recent llama-cli enters chat mode when the model has a chat template and
+        // then waits for more input; each run here is a single prompt
+        "-no-cnv".into(),
+    ];
+    let optional = [
+        ("--top-k", cfg.top_k.map(|v| v.to_string())),
+        ("--top-p", cfg.top_p.map(|v| v.to_string())),
+        ("--min-p", cfg.min_p.map(|v| v.to_string())),
+        ("--repeat-penalty", cfg.repeat_penalty.map(|v| v.to_string())),
+        ("--seed", cfg.seed.map(|v| v.to_string())),
+    ];
+    for (flag, value) in optional {
+        if let Some(value) = value {
+            args.push(flag.into());
+            args.push(value);
+        }
+    }
+    args
+}
+
+// Generate with the session's llama.cpp server if there is one, else a new CLI run
+fn run_model(
+    cfg: &GemmaConfig,
+    template: &Template,
+    prompt: &str,
+    cancel: &CancelToken,
+    on_token: &mut dyn FnMut(&str),
+) -> Result<String, TranslateError> {
+    let Some(server) = &cfg.server else {
+        return try_llama_cpp_cli(cfg, template, prompt, cancel, on_token);
+    };
+    let output = server.complete(cfg, template, prompt, cancel)?;
+    on_token(&output);
+    Ok(output)
 }
 
 // Try to use llama.cpp command line interface if available
-fn try_llama_cpp_cli(model_path: &str, prompt: &str, n_ctx: usize) -> Result<String> {
-    // Look for common llama.cpp executable names
-    let executables = ["llama", "llama-cli", "main", "./llama.cpp/main"];
-    
-    for exe in &executables {
-        if let Ok(output) = Command::new(exe)
-            .arg("-m")
-            .arg(model_path)
-            .arg("-p")
-            .arg(prompt)
-            .arg("-c")
-            .arg(n_ctx.to_string())
-            .arg("-n")
-            .arg("256")
-            .arg("--temp")
-            .arg("0.1")
-            .arg("-b")
-            .arg("1")
-            .output()
-        {
-            if output.status.success() {
-                let result = String::from_utf8_lossy(&output.stdout);
-                // Extract just the model output, removing the prompt echo
-                if let Some(model_start) = result.find("<start_of_turn>model") {
-                    if let Some(actual_output) = result[model_start..].find('\n') {
-                        let translation = result[model_start + actual_output + 1..].trim();
-                        if !translation.is_empty() {
-                            return Ok(translation.to_string());
-                        }
-                    }
+fn try_llama_cpp_cli(
+    cfg: &GemmaConfig,
+    template: &Template,
+    prompt: &str,
+    cancel: &CancelToken,
+    on_token: &mut dyn FnMut(&str),
+) -> Result<String, TranslateError> {
+    // Report the most specific failure: an executable that ran but failed beats none found
+    let mut error = TranslateError::BackendUnavailable;
+    
+    let args = llama_args(cfg, prompt);
+    // The model's own end-of-turn token ends generation as well as the configured stops
+    let stop: Vec<String> = cfg.stop.iter().chain(&template.stop).cloned().collect();
+    // llama.cpp echoes the prompt, so the answer follows the last marker it contains
+    let marker = Marker { text: &template.response_marker, nth: prompt.matches(template.response_marker.as_str()).count().max(1) };
+    for exe in &LLAMA_EXECUTABLES {
+        let child = Command::new(exe)
+            .args(&args)
+            // Never read the terminal, which in the REPL holds the user's next lines
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let Ok(mut child) = child else { continue };
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let child = Arc::new(Mutex::new(child));
+        let watchdog = Watchdog::start(child.clone(), cfg.timeout, cancel.clone());
+        
+        // Drain stderr on its own thread so a chatty run cannot block on a full pipe;
+        // it carries the timing summary used for metrics
+        let stderr = stderr.map(|mut stderr| {
+            std::thread::spawn(move || {
+                let mut log = String::new();
+                let _ = stderr.read_to_string(&mut log);
+                log
+            })
+        });
+        
+        let output = match stdout {
+            Some(stdout) => stream_model_output(stdout, &marker, &stop, on_token),
+            None => ModelOutput { text: String::new(), stopped: false },
+        };
+        // Nothing after a stop sequence is wanted, so don't wait for the run to end
+        if output.stopped {
+            let _ = child.lock().unwrap().kill();
+        }
+        
+        let aborted = watchdog.finish();
+        if let Some(log) = stderr.and_then(|handle| handle.join().ok()) {
+            record_llama_timings(&log);
+        }
+        
+        let status = child.lock().unwrap().wait();
+        // A terminal Ctrl-C also reaches llama.cpp directly, so check the token too
+        if let Some(error) = aborted {
+            return Err(error);
+        }
+        if cancel.is_cancelled() {
+            return Err(TranslateError::Cancelled);
+        }
+        
+        match status {
+            Ok(status) if status.success() || output.stopped => {
+                let translation = output.text.trim();
+                if !translation.is_empty() {
+                    return Ok(translation.to_string());
                 }
+                error = TranslateError::EmptyOutput;
+            }
+            Ok(status) => {
+                error = TranslateError::ProcessFailed { exe: exe.to_string(), reason: status.to_string() };
+            }
+            Err(e) => {
+                error = TranslateError::ProcessFailed { exe: exe.to_string(), reason: e.to_string() };
             }
         }
     }
     
-    Err(anyhow!("No working llama.cpp executable found"))
+    Err(error)
+}
+
+// Kills a llama.cpp child once its deadline passes or the request is cancelled.
+// Killing the process closes its stdout, which unblocks the reader.
+struct Watchdog {
+    done: Arc<AtomicBool>,
+    handle: std::thread::JoinHandle<Option<TranslateError>>,
+}
+
+impl Watchdog {
+    fn start(child: Arc<Mutex<std::process::Child>>, timeout: Duration, cancel: CancelToken) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let finished = done.clone();
+        let deadline = Instant::now() + timeout;
+        let handle = std::thread::spawn(move || {
+            while !finished.load(Ordering::Relaxed) {
+                let reason = if cancel.is_cancelled() {
+                    Some(TranslateError::Cancelled)
+                } else if Instant::now() >= deadline {
+                    Some(TranslateError::Timeout(timeout))
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    log::warn!("Stopping llama.cpp: {}", reason);
+                    let _ = child.lock().unwrap().kill();
+                    return Some(reason);
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            None
+        });
+        Self { done, handle }
+    }
+    
+    // Stop watching and report whether the process was killed, and why.
+    fn finish(self) -> Option<TranslateError> {
+        self.done.store(true, Ordering::Relaxed);
+        self.handle.join().ok().flatten()
+    }
+}
+
+// Pick the load and eval timings out of llama.cpp's perf summary, e.g.
+//   llama_perf_context_print:        load time =     733.86 ms
+//   llama_perf_context_print:        eval time =     601.83 ms /    15 runs   (...)
+// Older builds print the same lines with a `llama_print_timings:` prefix.
+fn record_llama_timings(log: &str) {
+    for line in log.lines() {
+        let Some((label, value)) = line.split_once('=') else { continue };
+        let label = label.trim_end();
+        let Some(ms) = value.split("ms").next().and_then(|v| v.trim().parse::<f64>().ok()) else { continue };
+        
+        if label.ends_with(" load time") {
+            metrics::set_model_load_seconds(ms / 1000.0);
+        } else if label.ends_with(" eval time") && !label.ends_with("prompt eval time") {
+            let tokens = value
+                .split_once('/')
+                .and_then(|(_, rest)| rest.split_whitespace().next())
+                .and_then(|n| n.parse::<u64>().ok());
+            if let Some(tokens) = tokens {
+                metrics::observe_generation(tokens, ms / 1000.0);
+            }
+        }
+    }
+}
+
+// Generated text, and whether it was cut at a stop sequence
+struct ModelOutput {
+    text: String,
+    stopped: bool,
+}
+
+// Where the answer starts in llama.cpp's output: after the `nth` occurrence of `text`
+struct Marker<'a> {
+    text: &'a str,
+    nth: usize,
+}
+
+// Read llama.cpp stdout as it is produced, forwarding everything after the prompt echo.
+// Reading ends at the first stop sequence, which is not part of the output.
+fn stream_model_output(
+    mut stdout: impl Read,
+    marker: &Marker,
+    stop: &[String],
+    on_token: &mut dyn FnMut(&str),
+) -> ModelOutput {
+    let mut raw = Vec::new();
+    let mut chunk = [0u8; 512];
+    let mut start: Option<usize> = None;
+    let mut emitted = 0;
+    
+    while let Ok(n) = stdout.read(&mut chunk) {
+        if n == 0 {
+            break;
+        }
+        raw.extend_from_slice(&chunk[..n]);
+        
+        // Only look at complete UTF-8; a multi-byte char may straddle two reads
+        let text = match std::str::from_utf8(&raw) {
+            Ok(text) => text,
+            Err(e) => std::str::from_utf8(&raw[..e.valid_up_to()]).unwrap_or_default(),
+        };
+        
+        if start.is_none() {
+            // Output begins after the marker and the whitespace following it; wait for
+            // the first other character, since the echo may end mid-whitespace
+            if let Some((at, _)) = text.match_indices(marker.text).nth(marker.nth - 1) {
+                let after = at + marker.text.len();
+                let rest = &text[after..];
+                let trimmed = rest.trim_start();
+                if !trimmed.is_empty() {
+                    start = Some(after + rest.len() - trimmed.len());
+                    emitted = after + rest.len() - trimmed.len();
+                }
+            }
+        }
+        let Some(start) = start else { continue };
+        
+        let generated = &text[start..];
+        if let Some(end) = stop.iter().filter_map(|s| generated.find(s.as_str())).min() {
+            if start + end > emitted {
+                on_token(&text[emitted..start + end]);
+            }
+            return ModelOutput { text: generated[..end].to_string(), stopped: true };
+        }
+        // Hold back a tail that could still grow into a stop sequence
+        let pending = stop.iter().map(|s| partial_suffix(generated, s)).max().unwrap_or(0);
+        if text.len() - pending > emitted {
+            on_token(&text[emitted..text.len() - pending]);
+            emitted = text.len() - pending;
+        }
+    }
+    
+    let text = String::from_utf8_lossy(&raw);
+    let Some(start) = start else {
+        return ModelOutput { text: String::new(), stopped: false };
+    };
+    if text.len() > emitted {
+        on_token(&text[emitted..]);
+    }
+    ModelOutput { text: text[start..].to_string(), stopped: false }
+}
+
+// Length of the longest proper prefix of `stop` that `text` ends with
+fn partial_suffix(text: &str, stop: &str) -> usize {
+    (1..stop.len())
+        .rev()
+        .find(|&len| stop.is_char_boundary(len) && text.ends_with(&stop[..len]))
+        .unwrap_or(0)
 }
@@ -0,0 +1,480 @@
+//! Reader for the header of a GGUF model file: format version, the key/value metadata
+//! (architecture, chat template, hyperparameters, ...) and the shape and type of each
+//! tensor. Tensor data is never read.
+
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Arrays longer than this (token lists, merges) only keep their length
+const MAX_ARRAY_ITEMS: u64 = 32;
+/// Longest string accepted; anything bigger means a corrupt or hostile file
+const MAX_STRING_BYTES: u64 = 16 * 1024 * 1024;
+/// ggml tensors have at most four dimensions
+const MAX_DIMS: u32 = 4;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    UInt(u64),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    /// `items` is empty when the array is longer than `MAX_ARRAY_ITEMS`
+    Array { len: u64, items: Vec<Value> },
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::UInt(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::String(v) => write!(f, "{:?}", v),
+            Value::Array { len, items } if items.len() as u64 == *len => {
+                let items: Vec<String> = items.iter().map(Value::to_string).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Value::Array { len, .. } => write!(f, "[{} items]", len),
+        }
+    }
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tensor {
+    pub name: String,
+    pub dims: Vec<u64>,
+    /// ggml type of the data, e.g. 12 for Q4_K
+    pub kind: u32,
+}
+
+impl Tensor {
+    pub fn elements(&self) -> u64 {
+        self.dims.iter().product()
+    }
+
+    pub fn type_name(&self) -> Option<&'static str> {
+        ggml_type_name(self.kind)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub version: u32,
+    pub entries: BTreeMap<String, Value>,
+    pub tensors: Vec<Tensor>,
+}
+
+impl Metadata {
+    /// `general.name`, e.g. `Gemma 2 2b It`.
+    pub fn name(&self) -> Option<&str> {
+        self.entries.get("general.name").and_then(Value::as_str)
+    }
+
+    /// `general.architecture`, e.g. `gemma2`, `llama` or `qwen2`.
+    pub fn architecture(&self) -> Option<&str> {
+        self.entries.get("general.architecture").and_then(Value::as_str)
+    }
+
+    /// The Jinja chat template shipped with the model, if any.
+    pub fn chat_template(&self) -> Option<&str> {
+        self.entries.get("tokenizer.chat_template").and_then(Value::as_str)
+    }
+
+    /// An unsigned integer entry, such as `general.file_type`.
+    pub fn uint(&self, key: &str) -> Option<u64> {
+        match self.entries.get(key)? {
+            Value::UInt(v) => Some(*v),
+            Value::Int(v) => u64::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+
+    /// A hyperparameter of the architecture, e.g. `context_length` for `gemma2.context_length`.
+    pub fn hparam(&self, name: &str) -> Option<u64> {
+        self.uint(&format!("{}.{}", self.architecture()?, name))
+    }
+
+    pub fn parameter_count(&self) -> u64 {
+        self.tensors.iter().map(Tensor::elements).sum()
+    }
+
+    /// Quantization, from `general.file_type` or else the type of most weights.
+    pub fn quantization(&self) -> Option<&'static str> {
+        if let Some(name) = self.uint("general.file_type").and_then(file_type_name) {
+            return Some(name);
+        }
+        let mut by_kind: BTreeMap<u32, u64> = BTreeMap::new();
+        for tensor in &self.tensors {
+            *by_kind.entry(tensor.kind).or_default() += tensor.elements();
+        }
+        by_kind.into_iter().max_by_key(|&(_, elements)| elements).and_then(|(kind, _)| ggml_type_name(kind))
+    }
+
+    /// Tokenizer model, e.g. `llama` (SentencePiece) or `gpt2` (BPE), and vocabulary size.
+    pub fn tokenizer(&self) -> (Option<&str>, Option<u64>) {
+        let model = self.entries.get("tokenizer.ggml.model").and_then(Value::as_str);
+        let vocab = match self.entries.get("tokenizer.ggml.tokens") {
+            Some(Value::Array { len, .. }) => Some(*len),
+            _ => None,
+        };
+        (model, vocab)
+    }
+}
+
+// llama.cpp's `llama_ftype`, the quantization a file was produced with
+fn file_type_name(file_type: u64) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        19 => "IQ2_XXS",
+        20 => "IQ2_XS",
+        21 => "Q2_K_S",
+        22 => "IQ3_XS",
+        23 => "IQ3_XXS",
+        24 => "IQ1_S",
+        25 => "IQ4_NL",
+        26 => "IQ3_S",
+        27 => "IQ3_M",
+        28 => "IQ2_S",
+        29 => "IQ2_M",
+        30 => "IQ4_XS",
+        31 => "IQ1_M",
+        32 => "BF16",
+        _ => return None,
+    })
+}
+
+// ggml's tensor types
+fn ggml_type_name(kind: u32) -> Option<&'static str> {
+    Some(match kind {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        6 => "Q5_0",
+        7 => "Q5_1",
+        8 => "Q8_0",
+        9 => "Q8_1",
+        10 => "Q2_K",
+        11 => "Q3_K",
+        12 => "Q4_K",
+        13 => "Q5_K",
+        14 => "Q6_K",
+        15 => "Q8_K",
+        16 => "IQ2_XXS",
+        17 => "IQ2_XS",
+        18 => "IQ3_XXS",
+        19 => "IQ1_S",
+        20 => "IQ4_NL",
+        21 => "IQ3_S",
+        22 => "IQ2_S",
+        23 => "IQ4_XS",
+        29 => "IQ1_M",
+        30 => "BF16",
+        _ => return None,
+    })
+}
+
+/// Read the metadata section of a GGUF file (versions 2 and 3).
+pub fn read_metadata(path: &Path) -> Result<Metadata> {
+    let file = File::open(path).map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let mut reader = BufReader::new(file);
+    parse(&mut reader).map_err(|e| anyhow!("{}: {}", path.display(), e))
+}
+
+fn parse(reader: &mut BufReader<File>) -> Result<Metadata> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).map_err(|_| anyhow!("not a GGUF file"))?;
+    if &magic != b"GGUF" {
+        return Err(anyhow!("not a GGUF file"));
+    }
+    let version = read_u32(reader)?;
+    if !(2..=3).contains(&version) {
+        return Err(anyhow!("unsupported GGUF version {}", version));
+    }
+    let tensor_count = read_u64(reader)?;
+    let kv_count = read_u64(reader)?;
+
+    let mut entries = BTreeMap::new();
+    for _ in 0..kv_count {
+        let key = read_string(reader)?;
+        let kind = read_u32(reader)?;
+        let value = read_value(reader, kind)?;
+        entries.insert(key, value);
+    }
+
+    let mut tensors = Vec::new();
+    for _ in 0..tensor_count {
+        let name = read_string(reader)?;
+        let n_dims = read_u32(reader)?;
+        if n_dims > MAX_DIMS {
+            return Err(anyhow!("tensor {} has {} dimensions", name, n_dims));
+        }
+        let dims = (0..n_dims).map(|_| read_u64(reader)).collect::<Result<_>>()?;
+        let kind = read_u32(reader)?;
+        let _offset = read_u64(reader)?;
+        tensors.push(Tensor { name, dims, kind });
+    }
+    Ok(Metadata { version, entries, tensors })
+}
+
+fn read_value(reader: &mut BufReader<File>, kind: u32) -> Result<Value> {
+    Ok(match kind {
+        0 => Value::UInt(read_n::<1>(reader)?[0] as u64),
+        1 => Value::Int(read_n::<1>(reader)?[0] as i8 as i64),
+        2 => Value::UInt(u16::from_le_bytes(read_n(reader)?) as u64),
+        3 => Value::Int(i16::from_le_bytes(read_n(reader)?) as i64),
+        4 => Value::UInt(read_u32(reader)? as u64),
+        5 => Value::Int(i32::from_le_bytes(read_n(reader)?) as i64),
+        6 => Value::Float(f32::from_le_bytes(read_n(reader)?) as f64),
+        7 => Value::Bool(read_n::<1>(reader)?[0] != 0),
+        8 => Value::String(read_string(reader)?),
+        9 => {
+            let item_kind = read_u32(reader)?;
+            let len = read_u64(reader)?;
+            // Unbounded nesting would recurse without limit on a hostile file
+            if item_kind == 9 {
+                return Err(anyhow!("nested metadata arrays are not supported"));
+            }
+            if len > MAX_ARRAY_ITEMS {
+                skip_array(reader, item_kind, len)?;
+                return Ok(Value::Array { len, items: Vec::new() });
+            }
+            let items = (0..len).map(|_| read_value(reader, item_kind)).collect::<Result<_>>()?;
+            Value::Array { len, items }
+        }
+        10 => Value::UInt(read_u64(reader)?),
+        11 => Value::Int(i64::from_le_bytes(read_n(reader)?)),
+        12 => Value::Float(f64::from_le_bytes(read_n(reader)?)),
+        other => return Err(anyhow!("unknown metadata value type {}", other)),
+    })
+}
+
+// Step over a long array without keeping it; tokenizer vocabularies run to 250k strings
+fn skip_array(reader: &mut BufReader<File>, item_kind: u32, len: u64) -> Result<()> {
+    let width: i64 = match item_kind {
+        0 | 1 | 7 => 1,
+        2 | 3 => 2,
+        4..=6 => 4,
+        10..=12 => 8,
+        8 => {
+            for _ in 0..len {
+                let bytes = read_string_len(reader)?;
+                reader.seek_relative(bytes as i64)?;
+            }
+            return Ok(());
+        }
+        9 => return Err(anyhow!("nested metadata arrays are not supported")),
+        other => return Err(anyhow!("unknown metadata value type {}", other)),
+    };
+    let bytes = i64::try_from(len).ok().and_then(|len| len.checked_mul(width)).ok_or_else(|| anyhow!("metadata array too large"))?;
+    reader.seek_relative(bytes)?;
+    Ok(())
+}
+
+fn read_n<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf).map_err(|_| anyhow!("file is truncated"))?;
+    Ok(buf)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_n(reader)?))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_n(reader)?))
+}
+
+fn read_string_len(reader: &mut impl Read) -> Result<u64> {
+    let len = read_u64(reader)?;
+    if len > MAX_STRING_BYTES {
+        return Err(anyhow!("metadata string of {} bytes is too long", len));
+    }
+    Ok(len)
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let len = read_string_len(reader)?;
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf).map_err(|_| anyhow!("file is truncated"))?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Builder for GGUF headers
+    struct Gguf(Vec<u8>);
+
+    impl Gguf {
+        fn new(version: u32, tensors: u64, kvs: u64) -> Self {
+            let mut gguf = Gguf(b"GGUF".to_vec());
+            gguf.u32(version).u64(tensors).u64(kvs);
+            gguf
+        }
+
+        fn u32(&mut self, v: u32) -> &mut Self {
+            self.0.extend_from_slice(&v.to_le_bytes());
+            self
+        }
+
+        fn u64(&mut self, v: u64) -> &mut Self {
+            self.0.extend_from_slice(&v.to_le_bytes());
+            self
+        }
+
+        fn string(&mut self, s: &str) -> &mut Self {
+            self.u64(s.len() as u64);
+            self.0.extend_from_slice(s.as_bytes());
+            self
+        }
+
+        fn kv_string(&mut self, key: &str, value: &str) -> &mut Self {
+            self.string(key).u32(8).string(value)
+        }
+    }
+
+    // `read_metadata` of `bytes` written to a scratch file
+    fn parse_bytes(bytes: &[u8]) -> Result<Metadata> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "gguf-test-{}-{}.gguf",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, bytes).unwrap();
+        let metadata = read_metadata(&path);
+        std::fs::remove_file(&path).unwrap();
+        metadata
+    }
+
+    fn model(version: u32) -> Gguf {
+        let mut gguf = Gguf::new(version, 1, 5);
+        gguf.kv_string("general.architecture", "gemma2").kv_string("general.name", "Gemma 2 2b It");
+        gguf.string("general.file_type").u32(4).u32(15);
+        gguf.string("gemma2.context_length").u32(10).u64(8192);
+        gguf.string("tokenizer.ggml.tokens").u32(9).u32(8).u64(40);
+        for i in 0..40 {
+            gguf.string(&format!("tok{}", i));
+        }
+        gguf.string("blk.0.attn_q.weight").u32(2).u64(4).u64(8).u32(12).u64(0);
+        gguf
+    }
+
+    #[test]
+    fn reads_v2_and_v3_headers() {
+        for version in [2, 3] {
+            let metadata = parse_bytes(&model(version).0).unwrap();
+            assert_eq!(metadata.version, version);
+            assert_eq!(metadata.architecture(), Some("gemma2"));
+            assert_eq!(metadata.name(), Some("Gemma 2 2b It"));
+            assert_eq!(metadata.hparam("context_length"), Some(8192));
+            assert_eq!(metadata.quantization(), Some("Q4_K_M"));
+            assert_eq!(metadata.parameter_count(), 32);
+            assert_eq!(metadata.tensors[0].name, "blk.0.attn_q.weight");
+            assert_eq!(metadata.tensors[0].type_name(), Some("Q4_K"));
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_length_of_long_arrays() {
+        let metadata = parse_bytes(&model(3).0).unwrap();
+        let tokens = &metadata.entries["tokenizer.ggml.tokens"];
+        assert!(matches!(tokens, Value::Array { len: 40, items } if items.is_empty()));
+        assert_eq!(tokens.to_string(), "[40 items]");
+        assert_eq!(metadata.tokenizer(), (None, Some(40)));
+    }
+
+    #[test]
+    fn keeps_short_arrays() {
+        let mut gguf = Gguf::new(3, 0, 1);
+        gguf.string("general.tags").u32(9).u32(5).u64(3).u32(1).u32(-2i32 as u32).u32(3);
+        let metadata = parse_bytes(&gguf.0).unwrap();
+        assert_eq!(metadata.entries["general.tags"].to_string(), "[1, -2, 3]");
+    }
+
+    #[test]
+    fn skips_long_arrays_of_fixed_width_values() {
+        let mut gguf = Gguf::new(3, 0, 2);
+        gguf.string("tokenizer.ggml.scores").u32(9).u32(6).u64(100);
+        gguf.0.extend_from_slice(&[0; 400]);
+        gguf.kv_string("general.name", "after");
+        let metadata = parse_bytes(&gguf.0).unwrap();
+        assert_eq!(metadata.name(), Some("after"));
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        let err = |bytes: &[u8]| parse_bytes(bytes).unwrap_err().to_string();
+        assert!(err(b"GGML").ends_with("not a GGUF file"));
+        assert!(err(b"").ends_with("not a GGUF file"));
+        assert!(err(&Gguf::new(1, 0, 0).0).ends_with("unsupported GGUF version 1"));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = model(3).0;
+        for len in [6, 20, 40, bytes.len() - 1] {
+            let err = parse_bytes(&bytes[..len]).unwrap_err().to_string();
+            assert!(err.ends_with("file is truncated"), "{}: {}", len, err);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_strings() {
+        let mut gguf = Gguf::new(3, 0, 1);
+        gguf.string("general.name").u32(8).u64(MAX_STRING_BYTES + 1);
+        let err = parse_bytes(&gguf.0).unwrap_err().to_string();
+        assert!(err.ends_with("metadata string of 16777217 bytes is too long"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_nested_arrays() {
+        for len in [1, MAX_ARRAY_ITEMS + 1] {
+            let mut gguf = Gguf::new(3, 0, 1);
+            gguf.string("general.nested").u32(9).u32(9).u64(len);
+            for _ in 0..1000 {
+                gguf.u32(9).u64(1);
+            }
+            let err = parse_bytes(&gguf.0).unwrap_err().to_string();
+            assert!(err.ends_with("nested metadata arrays are not supported"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn rejects_too_many_dimensions() {
+        let mut gguf = Gguf::new(3, 1, 0);
+        gguf.string("t").u32(5);
+        assert!(parse_bytes(&gguf.0).unwrap_err().to_string().ends_with("tensor t has 5 dimensions"));
+    }
+}
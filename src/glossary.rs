@@ -0,0 +1,329 @@
+//! Terminology that must be translated a fixed way, such as product names and medical
+//! terms. Glossaries are loaded from CSV or TBX, either per language pair from the
+//! `[glossaries]` config table or ad hoc for a REPL session or a single request.
+
+use crate::gemma::Direction;
+use crate::xml;
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GlossaryEntry {
+    pub source: String,
+    pub target: String,
+}
+
+/// Term list injected into the translation prompt.
+#[derive(Debug, Clone, Default)]
+pub struct Glossary {
+    pub entries: Vec<GlossaryEntry>,
+}
+
+lazy_static! {
+    /// Glossaries from the config, per language pair such as `es-en`
+    static ref GLOSSARIES: Mutex<HashMap<String, Glossary>> = Mutex::new(HashMap::new());
+}
+
+/// Load the `[glossaries]` table, replacing any glossaries loaded before.
+pub fn configure(paths: &BTreeMap<String, String>) -> Result<()> {
+    let mut loaded = HashMap::new();
+    for (pair, path) in paths {
+        let dir = Direction::from_str(pair).ok_or_else(|| anyhow!("Unsupported glossary language pair: {}", pair))?;
+        loaded.insert(pair.clone(), Glossary::load(Path::new(path), dir)?);
+    }
+    *GLOSSARIES.lock().unwrap() = loaded;
+    Ok(())
+}
+
+/// Terms for translating `input` in direction `dir`: the configured glossary for the
+/// pair plus `extra`, whose entries replace configured ones with the same source term.
+pub fn relevant_terms(dir: Direction, extra: Option<&Glossary>, input: &str) -> Vec<GlossaryEntry> {
+    let glossaries = GLOSSARIES.lock().unwrap();
+    let configured = glossaries.get(dir.as_str()).map(|g| g.entries.as_slice()).unwrap_or_default();
+    let extra = extra.map(|g| g.entries.as_slice()).unwrap_or_default();
+
+    let overridden = |e: &GlossaryEntry| extra.iter().any(|x| x.source.eq_ignore_ascii_case(&e.source));
+    let merged: Vec<&GlossaryEntry> = configured.iter().filter(|e| !overridden(e)).chain(extra).collect();
+    relevant(&merged, input).into_iter().cloned().collect()
+}
+
+/// Terms whose required translation is missing from `output`.
+pub fn violations(terms: &[GlossaryEntry], output: &str) -> Vec<GlossaryEntry> {
+    let haystack = output.to_lowercase();
+    terms
+        .iter()
+        .filter(|t| !t.target.is_empty() && word_matches(&haystack, &t.target.to_lowercase()).is_empty())
+        .cloned()
+        .collect()
+}
+
+impl Glossary {
+    /// Load a glossary by file extension: `.tbx` (or `.xml`) as TBX, anything else as CSV.
+    /// `dir` picks the languages out of a multilingual TBX file; CSV files are taken as
+    /// already being in that direction.
+    pub fn load(path: &Path, dir: Direction) -> Result<Self> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+        match extension.as_str() {
+            "tbx" | "xml" => Self::load_tbx(path, dir),
+            _ => Self::load_csv(path),
+        }
+    }
+
+    /// Build a glossary from `source -> target` pairs, as sent with a request.
+    #[cfg(feature = "ui")]
+    pub fn from_terms(terms: &BTreeMap<String, String>) -> Result<Self> {
+        let mut entries = Vec::with_capacity(terms.len());
+        for (source, target) in terms {
+            let (source, target) = (source.trim(), target.trim());
+            if source.is_empty() || target.is_empty() {
+                return Err(anyhow!("Glossary terms must not be empty"));
+            }
+            entries.push(GlossaryEntry { source: source.to_string(), target: target.to_string() });
+        }
+        Ok(Glossary { entries })
+    }
+
+    /// Load a two-column `source,target` CSV. A `source,target` header row is skipped.
+    pub fn load_csv(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read glossary {}: {}", path.display(), e))?;
+
+        let mut entries = Vec::new();
+        for (lineno, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let fields = split_csv_line(line);
+            if fields.len() < 2 {
+                return Err(anyhow!("{}:{}: expected `source,target`", path.display(), lineno + 1));
+            }
+            if lineno == 0 && fields[0].eq_ignore_ascii_case("source") {
+                continue;
+            }
+            entries.push(GlossaryEntry {
+                source: fields[0].trim().to_string(),
+                target: fields[1].trim().to_string(),
+            });
+        }
+
+        log::info!("Loaded {} glossary terms from {}", entries.len(), path.display());
+        Ok(Glossary { entries })
+    }
+
+    /// Load the terms for `dir` from a TBX file. Both TBX-Basic (`termEntry`/`langSet`)
+    /// and TBX v3 (`conceptEntry`/`langSec`) layouts are read; the first term per
+    /// language of each concept is used. Concepts missing either language are skipped.
+    pub fn load_tbx(path: &Path, dir: Direction) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read glossary {}: {}", path.display(), e))?;
+        let entries = parse_tbx(&text, dir).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        log::info!("Loaded {} {} glossary terms from {}", entries.len(), dir.as_str(), path.display());
+        Ok(Glossary { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+// Entries whose source term occurs in `input`, matched case-insensitively on word
+// boundaries. Entries only found inside a longer matching term are left out: with both
+// "heart" and "heart attack" in the glossary, "heart attack" alone applies to it.
+fn relevant<'a>(entries: &[&'a GlossaryEntry], input: &str) -> Vec<&'a GlossaryEntry> {
+    let haystack = input.to_lowercase();
+    let matched: Vec<(&GlossaryEntry, Vec<(usize, usize)>)> = entries
+        .iter()
+        .filter(|e| !e.source.trim().is_empty())
+        .map(|e| (*e, word_matches(&haystack, &e.source.to_lowercase())))
+        .filter(|(_, spans)| !spans.is_empty())
+        .collect();
+
+    matched
+        .iter()
+        .filter(|(entry, spans)| {
+            spans.iter().any(|&(start, end)| {
+                !matched.iter().any(|(other, others)| {
+                    other.source.len() > entry.source.len()
+                        && others.iter().any(|&(s, e)| s <= start && end <= e)
+                })
+            })
+        })
+        .map(|(entry, _)| *entry)
+        .collect()
+}
+
+// Byte ranges where `needle` occurs in `haystack` without letters or digits on either side
+fn word_matches(haystack: &str, needle: &str) -> Vec<(usize, usize)> {
+    let needle = needle.trim();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+    haystack
+        .match_indices(needle)
+        .map(|(start, _)| (start, start + needle.len()))
+        .filter(|&(start, end)| {
+            let before = haystack[..start].chars().next_back();
+            let after = haystack[end..].chars().next();
+            !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+        .collect()
+}
+
+fn parse_tbx(text: &str, dir: Direction) -> Result<Vec<GlossaryEntry>> {
+    let (source_lang, target_lang) = dir.languages();
+    let mut concepts = xml::elements(text, "termEntry")?;
+    concepts.extend(xml::elements(text, "conceptEntry")?);
+
+    let mut entries = Vec::new();
+    for concept in concepts {
+        let mut sets = xml::elements(concept.content, "langSet")?;
+        sets.extend(xml::elements(concept.content, "langSec")?);
+
+        let mut source = None;
+        let mut target = None;
+        for set in sets {
+            let Some(lang) = set.lang() else { continue };
+            let slot = match lang.as_str() {
+                l if l == source_lang => &mut source,
+                l if l == target_lang => &mut target,
+                _ => continue,
+            };
+            if slot.is_none() {
+                *slot = xml::first(set.content, "term")?
+                    .map(|term| xml::unescape(&xml::strip_tags(term)).trim().to_string())
+                    .filter(|term| !term.is_empty());
+            }
+        }
+        if let (Some(source), Some(target)) = (source, target) {
+            entries.push(GlossaryEntry { source, target });
+        }
+    }
+    Ok(entries)
+}
+
+// Minimal CSV field splitter: handles quoted fields and doubled quotes, nothing more.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(source: &str, target: &str) -> GlossaryEntry {
+        GlossaryEntry { source: source.to_string(), target: target.to_string() }
+    }
+
+    fn sources(entries: &[GlossaryEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.source.as_str()).collect()
+    }
+
+    fn scratch(name: &str, text: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("glossary-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn splits_quoted_csv_fields() {
+        assert_eq!(split_csv_line("a,b"), ["a", "b"]);
+        assert_eq!(split_csv_line(r#""Smith, John","Smith, Juan""#), ["Smith, John", "Smith, Juan"]);
+        assert_eq!(split_csv_line(r#""say ""hi""",decir "hola""#), [r#"say "hi""#, "decir hola"]);
+        assert_eq!(split_csv_line("a,,c,"), ["a", "", "c", ""]);
+    }
+
+    #[test]
+    fn loads_csv_glossaries() {
+        let path = scratch("terms.csv", "Source,Target\n# comment\n\n\"heart, left\", corazón izquierdo \nstent,stent\n");
+        let glossary = Glossary::load(&path, Direction::EnToEs).unwrap();
+        assert_eq!(sources(&glossary.entries), ["heart, left", "stent"]);
+        assert_eq!(glossary.entries[0].target, "corazón izquierdo");
+
+        std::fs::write(&path, "stent,stent\nno target here\n").unwrap();
+        let err = Glossary::load_csv(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.ends_with(":2: expected `source,target`"), "{}", err);
+    }
+
+    #[test]
+    fn reads_the_direction_from_tbx() {
+        let tbx = r#"<tbx><text><body>
+          <termEntry id="1">
+            <langSet xml:lang="en-US"><tig><term>heart attack</term></tig><tig><term>MI</term></tig></langSet>
+            <langSet xml:lang="es"><tig><term>infarto &amp; co</term></tig></langSet>
+          </termEntry>
+          <conceptEntry id="2">
+            <langSec xml:lang="es"><termSec><term>válvula</term></termSec></langSec>
+            <langSec xml:lang="en"><termSec><term><hi>valve</hi></term></termSec></langSec>
+          </conceptEntry>
+          <termEntry id="3"><langSet xml:lang="en"><tig><term>orphan</term></tig></langSet></termEntry>
+          <termEntry id="4">
+            <langSet xml:lang="fr"><tig><term>coeur</term></tig></langSet>
+            <langSet xml:lang="es"><tig><term>corazón</term></tig></langSet>
+          </termEntry>
+        </body></text></tbx>"#;
+        let en_es = parse_tbx(tbx, Direction::EnToEs).unwrap();
+        assert_eq!(sources(&en_es), ["heart attack", "valve"]);
+        assert_eq!(en_es[0].target, "infarto & co");
+        let es_en = parse_tbx(tbx, Direction::EsToEn).unwrap();
+        assert_eq!(sources(&es_en), ["infarto & co", "válvula"]);
+        assert_eq!(es_en[1].target, "valve");
+    }
+
+    #[test]
+    fn prefers_the_longest_matching_term() {
+        let terms = [entry("heart", "corazón"), entry("heart attack", "infarto"), entry("art", "arte")];
+        let terms: Vec<&GlossaryEntry> = terms.iter().collect();
+        let found = |input: &str| relevant(&terms, input).into_iter().map(|e| e.source.as_str()).collect::<Vec<_>>();
+        assert_eq!(found("Signs of a Heart Attack."), ["heart attack"]);
+        assert_eq!(found("A heart attack strains the heart."), ["heart", "heart attack"]);
+        // Only on word boundaries
+        assert_eq!(found("Hearty earth, smart artist"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn finds_missing_target_terms() {
+        let terms = [entry("heart attack", "infarto"), entry("stent", "Stent"), entry("valve", "")];
+        let missing = violations(&terms, "Tras el infartos, un STENT.");
+        assert_eq!(sources(&missing), ["heart attack"]);
+    }
+
+    // The only test touching the configured glossaries
+    #[test]
+    fn request_terms_override_configured_ones() {
+        let path = scratch("configured.csv", "heart,corazón\nvalve,válvula\n");
+        let paths = BTreeMap::from([("en-es".to_string(), path.display().to_string())]);
+        configure(&paths).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let extra = Glossary { entries: vec![entry("Heart", "cardio"), entry("stent", "stent")] };
+        let terms = relevant_terms(Direction::EnToEs, Some(&extra), "heart valve stent");
+        assert_eq!(sources(&terms), ["valve", "Heart", "stent"]);
+        assert_eq!(terms[1].target, "cardio");
+        assert!(relevant_terms(Direction::EsToEn, None, "heart valve").is_empty());
+
+        let bad = BTreeMap::from([("en-fr".to_string(), "x.csv".to_string())]);
+        assert!(configure(&bad).is_err());
+    }
+}
@@ -0,0 +1,436 @@
+//! Background jobs for long texts and recordings: `POST /jobs` queues one, worker
+//! threads transcribe and translate it while reporting progress, and clients poll
+//! `GET /jobs/{id}` for the result or cancel with `DELETE /jobs/{id}`.
+//!
+//! Jobs live in memory only. Finished ones are dropped after the configured TTL, and
+//! the queue is bounded so a burst of uploads is refused rather than buffered.
+
+use crate::asr::{AsrClient, AsrError, AudioInput};
+use crate::cancel::CancelToken;
+use crate::error::{Error, ErrorCode};
+use crate::gemma::{translate_with_progress, Direction, GemmaConfig, Translation};
+use crate::glossary::GlossaryEntry;
+use crate::models::ActiveModel;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+pub enum JobRequest {
+    Text { direction: Direction, text: String },
+    Audio { direction: Direction, audio: AudioInput },
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct JobResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<String>,
+    pub translated: String,
+    /// True when any part of the text came from the phrasebook fallback
+    pub fallback: bool,
+    /// Glossary terms whose required translation is missing, as from `/translate`
+    pub glossary_violations: Vec<GlossaryEntry>,
+}
+
+impl JobResult {
+    fn new(transcript: Option<String>, translation: Translation) -> Self {
+        Self {
+            transcript,
+            translated: translation.text,
+            fallback: translation.fallback,
+            glossary_violations: translation.violations,
+        }
+    }
+}
+
+/// Public view of a job returned by `GET /jobs/{id}`.
+#[derive(Serialize, Clone)]
+pub struct JobView {
+    pub id: String,
+    pub kind: &'static str,
+    pub direction: &'static str,
+    pub status: JobStatus,
+    pub progress: f32,
+    pub result: Option<JobResult>,
+    pub error: Option<String>,
+    pub code: Option<ErrorCode>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+struct Job {
+    view: JobView,
+    request: Option<JobRequest>,
+    cancel: CancelToken,
+    finished_at: Option<Instant>,
+}
+
+struct State {
+    jobs: HashMap<String, Job>,
+    queue: VecDeque<String>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+    ttl: Duration,
+    max_queue: usize,
+    next_id: AtomicU64,
+}
+
+/// In-memory job store with a bounded FIFO queue. Finished jobs are kept for `ttl`
+/// so clients can collect results, then dropped.
+#[derive(Clone)]
+pub struct JobStore {
+    shared: Arc<Shared>,
+}
+
+/// Handle given to a running job for progress reporting and cancellation checks.
+pub struct JobContext {
+    store: JobStore,
+    id: String,
+    cancel: CancelToken,
+}
+
+impl JobContext {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub fn set_progress(&self, progress: f32) {
+        self.store.update(&self.id, |view| view.progress = progress.clamp(0.0, 1.0));
+    }
+}
+
+impl JobStore {
+    pub fn new(ttl: Duration, max_queue: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State { jobs: HashMap::new(), queue: VecDeque::new() }),
+                wake: Condvar::new(),
+                ttl,
+                max_queue,
+                next_id: AtomicU64::new(1),
+            }),
+        }
+    }
+
+    /// Queue a job and return its id. Fails when the queue is full.
+    pub fn submit(&self, request: JobRequest) -> Result<String, Error> {
+        let mut state = self.shared.state.lock().unwrap();
+        self.prune(&mut state);
+        if state.queue.len() >= self.shared.max_queue {
+            return Err(Error::QueueFull(format!("Job queue is full ({} pending)", state.queue.len())));
+        }
+
+        let id = self.new_id();
+        let (kind, direction) = match &request {
+            JobRequest::Text { direction, .. } => ("text", direction.as_str()),
+            JobRequest::Audio { direction, .. } => ("audio", direction.as_str()),
+        };
+        let now = unix_now();
+        state.jobs.insert(id.clone(), Job {
+            view: JobView {
+                id: id.clone(),
+                kind,
+                direction,
+                status: JobStatus::Queued,
+                progress: 0.0,
+                result: None,
+                error: None,
+                code: None,
+                created_at: now,
+                updated_at: now,
+            },
+            request: Some(request),
+            cancel: CancelToken::new(),
+            finished_at: None,
+        });
+        state.queue.push_back(id.clone());
+        drop(state);
+
+        self.shared.wake.notify_one();
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobView> {
+        let mut state = self.shared.state.lock().unwrap();
+        self.prune(&mut state);
+        state.jobs.get(id).map(|job| job.view.clone())
+    }
+
+    /// Request cancellation. Queued jobs are cancelled at once; running jobs abort their
+    /// current ASR call or llama.cpp run. Returns the status after the request, or `None` if unknown.
+    pub fn cancel(&self, id: &str) -> Option<JobStatus> {
+        let mut state = self.shared.state.lock().unwrap();
+        let job = state.jobs.get_mut(id)?;
+        if job.view.status.is_finished() {
+            return Some(job.view.status);
+        }
+
+        job.cancel.cancel();
+        if job.view.status == JobStatus::Queued {
+            job.request = None;
+            finish(job, JobStatus::Cancelled);
+            state.queue.retain(|queued| queued != id);
+            return Some(JobStatus::Cancelled);
+        }
+        Some(job.view.status)
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.shared.state.lock().unwrap().queue.len()
+    }
+
+    pub fn max_queue(&self) -> usize {
+        self.shared.max_queue
+    }
+
+    /// Start `count` worker threads that drain the queue one job at a time.
+    pub fn spawn_workers(&self, count: usize, model: Arc<ActiveModel>, asr: AsrClient) {
+        for n in 0..count.max(1) {
+            let store = self.clone();
+            let model = model.clone();
+            let asr = asr.clone();
+            std::thread::Builder::new()
+                .name(format!("job-worker-{}", n))
+                .spawn(move || store.worker_loop(&model, &asr))
+                .expect("failed to spawn job worker");
+        }
+    }
+
+    fn worker_loop(&self, active: &ActiveModel, asr: &AsrClient) {
+        // The ASR client is async; each worker drives it on a small runtime of its own
+        let rt = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(rt) => rt,
+            Err(e) => {
+                log::error!("Job worker could not start a runtime: {}", e);
+                return;
+            }
+        };
+
+        loop {
+            let (ctx, request) = {
+                let mut state = self.shared.state.lock().unwrap();
+                loop {
+                    self.prune(&mut state);
+                    if let Some(id) = state.queue.pop_front() {
+                        let Some(job) = state.jobs.get_mut(&id) else { continue };
+                        let Some(request) = job.request.take() else { continue };
+                        job.view.status = JobStatus::Running;
+                        job.view.updated_at = unix_now();
+                        let ctx = JobContext { store: self.clone(), id, cancel: job.cancel.clone() };
+                        break (ctx, request);
+                    }
+                    state = self.shared.wake.wait_timeout(state, Duration::from_secs(30)).unwrap().0;
+                }
+            };
+
+            log::info!("Running job {}", ctx.id);
+            // A job finishes on the model it started with, even if it is switched meanwhile
+            let model = active.get();
+            let outcome = run_job(&ctx, request, &model.gemma, asr, &rt);
+            drop(model);
+
+            let mut state = self.shared.state.lock().unwrap();
+            if let Some(job) = state.jobs.get_mut(&ctx.id) {
+                match outcome {
+                    _ if ctx.is_cancelled() => finish(job, JobStatus::Cancelled),
+                    Ok(result) => {
+                        job.view.result = Some(result);
+                        job.view.progress = 1.0;
+                        finish(job, JobStatus::Completed);
+                    }
+                    Err(e) => {
+                        log::error!("Job {} failed: {}", ctx.id, e);
+                        job.view.error = Some(e.to_string());
+                        job.view.code = Some(ErrorCode::of(&e));
+                        finish(job, JobStatus::Failed);
+                    }
+                }
+            }
+        }
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut JobView)) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(job) = state.jobs.get_mut(id) {
+            f(&mut job.view);
+            job.view.updated_at = unix_now();
+        }
+    }
+
+    fn prune(&self, state: &mut State) {
+        let ttl = self.shared.ttl;
+        state.jobs.retain(|_, job| !matches!(job.finished_at, Some(at) if at.elapsed() >= ttl));
+    }
+
+    fn new_id(&self) -> String {
+        let seq = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .subsec_nanos();
+        format!("job-{:x}{:08x}", seq, nanos)
+    }
+}
+
+fn finish(job: &mut Job, status: JobStatus) {
+    job.view.status = status;
+    job.view.updated_at = unix_now();
+    job.finished_at = Some(Instant::now());
+}
+
+fn run_job(
+    ctx: &JobContext,
+    request: JobRequest,
+    gemma: &GemmaConfig,
+    asr: &AsrClient,
+    rt: &tokio::runtime::Runtime,
+) -> Result<JobResult> {
+    match request {
+        JobRequest::Text { direction, text } => {
+            let translation = translate_document(ctx, gemma, direction, &text, 0.0)?;
+            Ok(JobResult::new(None, translation))
+        }
+        JobRequest::Audio { direction, audio } => {
+            let transcript = rt.block_on(async {
+                tokio::select! {
+                    result = asr.transcribe(&audio) => result,
+                    _ = ctx.cancel.cancelled() => Err(AsrError::Cancelled),
+                }
+            })?;
+            ctx.set_progress(0.3);
+            if ctx.is_cancelled() {
+                return Err(anyhow!("Job cancelled"));
+            }
+            let translation = translate_document(ctx, gemma, direction, &transcript, 0.3)?;
+            Ok(JobResult::new(Some(transcript), translation))
+        }
+    }
+}
+
+// Translate the whole text at once, so sentences share chunks and each chunk gets the
+// ones before it as context; progress moves on as each chunk is done.
+fn translate_document(
+    ctx: &JobContext,
+    gemma: &GemmaConfig,
+    direction: Direction,
+    text: &str,
+    base_progress: f32,
+) -> Result<Translation> {
+    let translation = translate_with_progress(gemma, direction, text, &ctx.cancel, &mut |done, total| {
+        ctx.set_progress(base_progress + (1.0 - base_progress) * done as f32 / total as f32);
+    })?;
+    Ok(translation)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> JobRequest {
+        JobRequest::Text { direction: Direction::EsToEn, text: text.to_string() }
+    }
+
+    // Take the next job off the queue the way a worker does
+    fn start(store: &JobStore) -> JobContext {
+        let mut state = store.shared.state.lock().unwrap();
+        let id = state.queue.pop_front().unwrap();
+        let job = state.jobs.get_mut(&id).unwrap();
+        job.request.take().unwrap();
+        job.view.status = JobStatus::Running;
+        JobContext { store: store.clone(), id, cancel: job.cancel.clone() }
+    }
+
+    fn status(store: &JobStore, id: &str) -> Option<JobStatus> {
+        store.get(id).map(|view| view.status)
+    }
+
+    #[test]
+    fn submit_fails_once_the_queue_is_full() {
+        let store = JobStore::new(Duration::from_secs(60), 2);
+        store.submit(text("uno")).unwrap();
+        store.submit(text("dos")).unwrap();
+        assert!(matches!(store.submit(text("tres")), Err(Error::QueueFull(_))));
+        assert_eq!(store.queue_len(), 2);
+
+        // A running job no longer takes a queue slot, and neither does a cancelled one
+        start(&store);
+        store.submit(text("tres")).unwrap();
+        assert!(matches!(store.submit(text("cuatro")), Err(Error::QueueFull(_))));
+        let queued = store.shared.state.lock().unwrap().queue[0].clone();
+        store.cancel(&queued);
+        store.submit(text("cuatro")).unwrap();
+    }
+
+    #[test]
+    fn cancelling_a_queued_job_takes_it_off_the_queue() {
+        let store = JobStore::new(Duration::from_secs(60), 4);
+        let first = store.submit(text("uno")).unwrap();
+        let second = store.submit(text("dos")).unwrap();
+
+        assert_eq!(store.cancel(&second), Some(JobStatus::Cancelled));
+        assert_eq!(status(&store, &second), Some(JobStatus::Cancelled));
+        assert_eq!(store.shared.state.lock().unwrap().queue, [first.as_str()]);
+        assert!(store.shared.state.lock().unwrap().jobs[&second].request.is_none());
+        // Cancelling again reports the final status; unknown ids report nothing
+        assert_eq!(store.cancel(&second), Some(JobStatus::Cancelled));
+        assert_eq!(store.cancel("job-0"), None);
+
+        // A running job is only asked to stop; the worker finishes it
+        let running = start(&store);
+        assert_eq!(store.cancel(&first), Some(JobStatus::Running));
+        assert!(running.is_cancelled());
+        assert_eq!(status(&store, &first), Some(JobStatus::Running));
+    }
+
+    #[test]
+    fn prune_drops_finished_jobs_after_the_ttl_but_never_running_ones() {
+        let ttl = Duration::from_millis(50);
+        let store = JobStore::new(ttl, 4);
+        let running = store.submit(text("uno")).unwrap();
+        let cancelled = store.submit(text("dos")).unwrap();
+        let queued = store.submit(text("tres")).unwrap();
+        let ctx = start(&store);
+        store.cancel(&cancelled);
+        assert_eq!(status(&store, &cancelled), Some(JobStatus::Cancelled));
+
+        std::thread::sleep(ttl * 2);
+        assert_eq!(status(&store, &cancelled), None);
+        assert_eq!(status(&store, &running), Some(JobStatus::Running));
+        assert_eq!(status(&store, &queued), Some(JobStatus::Queued));
+
+        // Once it finishes, the running job gets its own TTL
+        ctx.set_progress(1.0);
+        finish(store.shared.state.lock().unwrap().jobs.get_mut(&running).unwrap(), JobStatus::Completed);
+        assert_eq!(status(&store, &running), Some(JobStatus::Completed));
+        std::thread::sleep(ttl * 2);
+        assert_eq!(status(&store, &running), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! A llama.cpp server kept running for an interactive session, so the model is loaded
+//! once instead of by a new llama.cpp process for every line.
+//!
+//! The server listens on a free loopback port and is spoken to with one plain HTTP/1.1
+//! request per completion. It runs in its own process group, so a terminal Ctrl-C only
+//! cancels the request, and is stopped when the handle is dropped or by [`stop_all`].
+
+use crate::cancel::CancelToken;
+use crate::gemma::{GemmaConfig, TranslateError};
+use crate::metrics;
+use crate::template::Template;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+
+// llama.cpp server executable names, tried in order
+const SERVER_EXECUTABLES: [&str; 3] = ["llama-server", "./llama.cpp/llama-server", "./llama.cpp/server"];
+
+// How often a wait checks for cancellation, its deadline and the server exiting
+const POLL: Duration = Duration::from_millis(50);
+
+lazy_static! {
+    // Every server started, for `stop_all`
+    static ref RUNNING: Mutex<Vec<Weak<Mutex<Child>>>> = Mutex::new(Vec::new());
+}
+
+/// Stop every server still running, for exits that skip destructors.
+pub fn stop_all() {
+    for child in RUNNING.lock().unwrap().drain(..).filter_map(|child| child.upgrade()) {
+        let _ = child.lock().unwrap().kill();
+    }
+}
+
+/// A running `llama-server` with the configured model loaded.
+pub struct LlamaServer {
+    exe: &'static str,
+    port: u16,
+    child: Arc<Mutex<Child>>,
+}
+
+impl std::fmt::Debug for LlamaServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LlamaServer").field("exe", &self.exe).field("port", &self.port).finish()
+    }
+}
+
+impl LlamaServer {
+    /// Start a server for `cfg.model_path` and wait up to `cfg.timeout` for the model to
+    /// load. Setting `cancel` gives up and stops the server.
+    pub fn start(cfg: &GemmaConfig, cancel: &CancelToken) -> Result<Self, TranslateError> {
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|listener| listener.local_addr())
+            .map(|addr| addr.port())
+            .map_err(|e| TranslateError::ProcessFailed { exe: "llama-server".to_string(), reason: format!("no free port: {}", e) })?;
+        let args = [
+            "-m".to_string(),
+            cfg.model_path.clone(),
+            "-c".to_string(),
+            cfg.n_ctx.to_string(),
+            "--host".to_string(),
+            Ipv4Addr::LOCALHOST.to_string(),
+            "--port".to_string(),
+            port.to_string(),
+        ];
+        let started = Instant::now();
+        for exe in SERVER_EXECUTABLES {
+            let mut command = Command::new(exe);
+            command.args(&args).stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::piped());
+            #[cfg(unix)]
+            std::os::unix::process::CommandExt::process_group(&mut command, 0);
+            let Ok(mut child) = command.spawn() else { continue };
+            // The server logs every request; keep it out of the session unless asked for
+            if let Some(stderr) = child.stderr.take() {
+                std::thread::spawn(move || {
+                    for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                        log::debug!("llama-server: {}", line);
+                    }
+                });
+            }
+            let child = Arc::new(Mutex::new(child));
+            RUNNING.lock().unwrap().push(Arc::downgrade(&child));
+            let server = Self { exe, port, child };
+            server.wait_ready(cfg.timeout, cancel)?;
+            log::info!("{} loaded {} on port {}", exe, cfg.model_path, port);
+            metrics::set_model_load_seconds(started.elapsed().as_secs_f64());
+            return Ok(server);
+        }
+        Err(TranslateError::ProcessFailed {
+            exe: "llama-server".to_string(),
+            reason: "not found on PATH or in ./llama.cpp".to_string(),
+        })
+    }
+
+    /// Generate a completion of the rendered `prompt` with the sampling settings of `cfg`.
+    /// Unlike the CLI the server does not echo the prompt, so this is only the answer.
+    pub fn complete(
+        &self,
+        cfg: &GemmaConfig,
+        template: &Template,
+        prompt: &str,
+        cancel: &CancelToken,
+    ) -> Result<String, TranslateError> {
+        if let Some(status) = self.exited() {
+            return Err(self.failed(format!("exited ({})", status)));
+        }
+        let stop: Vec<&String> = cfg.stop.iter().chain(&template.stop).collect();
+        let mut body = serde_json::json!({
+            "prompt": prompt,
+            "n_predict": cfg.max_tokens,
+            "temperature": cfg.temperature,
+            "stop": stop,
+            // Reuse the evaluated system prompt from the previous line
+            "cache_prompt": true,
+        });
+        let optional = [
+            ("top_k", cfg.top_k.map(serde_json::Value::from)),
+            ("top_p", cfg.top_p.map(serde_json::Value::from)),
+            ("min_p", cfg.min_p.map(serde_json::Value::from)),
+            ("repeat_penalty", cfg.repeat_penalty.map(serde_json::Value::from)),
+            ("seed", cfg.seed.map(serde_json::Value::from)),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                body[key] = value;
+            }
+        }
+
+        let (status, response) = self.request("POST", "/completion", &body.to_string(), cfg.timeout, cancel)?;
+        let response: serde_json::Value =
+            serde_json::from_slice(&response).map_err(|e| self.failed(format!("invalid response: {}", e)))?;
+        if status != 200 {
+            let message = response["error"]["message"].as_str().map(str::to_string);
+            return Err(self.failed(message.unwrap_or_else(|| format!("HTTP {}", status))));
+        }
+        let timings = &response["timings"];
+        if let (Some(tokens), Some(ms)) = (timings["predicted_n"].as_u64(), timings["predicted_ms"].as_f64()) {
+            metrics::observe_generation(tokens, ms / 1000.0);
+        }
+        Ok(response["content"].as_str().unwrap_or_default().to_string())
+    }
+
+    // `/health` answers 503 while the model loads and 200 once it can take requests
+    fn wait_ready(&self, timeout: Duration, cancel: &CancelToken) -> Result<(), TranslateError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = self.exited() {
+                return Err(self.failed(format!("exited while loading the model ({})", status)));
+            }
+            if let Ok((200, _)) = self.request("GET", "/health", "", Duration::from_secs(1), cancel) {
+                return Ok(());
+            }
+            if cancel.is_cancelled() {
+                return Err(TranslateError::Cancelled);
+            }
+            if Instant::now() >= deadline {
+                return Err(TranslateError::Timeout(timeout));
+            }
+            std::thread::sleep(POLL * 4);
+        }
+    }
+
+    // One request over a fresh connection, read to the end. Dropping the connection on
+    // cancellation or timeout makes the server abandon the generation.
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: &str,
+        timeout: Duration,
+        cancel: &CancelToken,
+    ) -> Result<(u16, Vec<u8>), TranslateError> {
+        let deadline = Instant::now() + timeout;
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, self.port)).map_err(|e| self.failed(e.to_string()))?;
+        stream.set_read_timeout(Some(POLL)).map_err(|e| self.failed(e.to_string()))?;
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            Ipv4Addr::LOCALHOST,
+            self.port,
+            body.len(),
+            body
+        )
+        .map_err(|e| self.failed(e.to_string()))?;
+
+        let mut raw = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => raw.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted) => {
+                    if cancel.is_cancelled() {
+                        return Err(TranslateError::Cancelled);
+                    }
+                    if Instant::now() >= deadline {
+                        return Err(TranslateError::Timeout(timeout));
+                    }
+                }
+                Err(e) => return Err(self.failed(e.to_string())),
+            }
+        }
+        parse_response(&raw).ok_or_else(|| self.failed("malformed HTTP response".to_string()))
+    }
+
+    fn exited(&self) -> Option<ExitStatus> {
+        self.child.lock().unwrap().try_wait().ok().flatten()
+    }
+
+    fn failed(&self, reason: String) -> TranslateError {
+        TranslateError::ProcessFailed { exe: self.exe.to_string(), reason }
+    }
+}
+
+impl Drop for LlamaServer {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap();
+        let _ = child.kill();
+        let _ = child.wait();
+        log::info!("Stopped {}", self.exe);
+    }
+}
+
+// Status and body of an HTTP/1.1 response, with a chunked body decoded
+fn parse_response(raw: &[u8]) -> Option<(u16, Vec<u8>)> {
+    let head_end = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..head_end]).ok()?;
+    let status = head.split_whitespace().nth(1)?.parse().ok()?;
+    let body = &raw[head_end + 4..];
+    let chunked = head.lines().skip(1).any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.starts_with("transfer-encoding:") && line.contains("chunked")
+    });
+    if !chunked {
+        return Some((status, body.to_vec()));
+    }
+
+    let mut decoded = Vec::new();
+    let mut rest = body;
+    loop {
+        let line_end = rest.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&rest[..line_end]).ok()?.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Some((status, decoded));
+        }
+        decoded.extend_from_slice(rest.get(..size)?);
+        rest = rest.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_sized_body() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 15\r\n\r\n{\"content\":\"a\"}";
+        assert_eq!(parse_response(raw), Some((200, b"{\"content\":\"a\"}".to_vec())));
+    }
+
+    #[test]
+    fn decodes_a_chunked_body() {
+        let raw = b"HTTP/1.1 503 Service Unavailable\r\nTransfer-Encoding: Chunked\r\n\r\n4\r\nWiki\r\n6;ext=1\r\npedia!\r\n0\r\n\r\n";
+        assert_eq!(parse_response(raw), Some((503, b"Wikipedia!".to_vec())));
+    }
+
+    #[test]
+    fn rejects_truncated_responses() {
+        assert_eq!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 3"), None);
+        assert_eq!(parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nA\r\nshort"), None);
+        assert_eq!(parse_response(b"garbage\r\n\r\n"), None);
+    }
+}
@@ -1,15 +1,35 @@
 mod asr;
+mod cache;
+mod cancel;
+mod cleanup;
+#[cfg(feature = "asr")] mod chunking;
+mod config;
+mod error;
 mod gemma;
+mod gguf;
+mod glossary;
+#[cfg(feature = "ui")] mod jobs;
+mod llama_server;
+mod memory;
+mod metrics;
+mod models;
 mod platform;
+mod protect;
+mod repl;
+mod segment;
+mod template;
+#[cfg(feature = "ui")] mod vad;
 #[cfg(feature = "ui")] mod ui;
+mod xml;
 
 #[cfg(feature = "realtime")]
-use crate::asr::transcribe_wav;
+use crate::asr::AsrClient;
 #[cfg(feature = "realtime")]
-use crate::asr::AsrConfig;
-#[cfg(feature = "realtime")]
-use crate::gemma::{translate, Direction, GemmaConfig};
-use clap::{ArgGroup, Parser};
+use crate::gemma::{translate, Direction};
+use crate::cancel::CancelToken;
+use crate::config::{AsrBackend, Config};
+use crate::error::ErrorCode;
+use clap::{ArgGroup, Parser, Subcommand};
 use log::LevelFilter;
 
 #[derive(Parser, Debug)]
@@ -24,37 +44,261 @@ struct Args {
     #[arg(long)]
     realtime: Option<u32>,
 
+    /// Config file (TOML). Defaults to $XDG_CONFIG_HOME/gemma-translator/config.toml and ./gemma-translator.toml
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_CONFIG")]
+    config: Option<String>,
+
     /// Direction: es-en or en-es
-    #[arg(long, value_parser = ["es-en", "en-es"])]
-    direction: String,
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_DIRECTION", value_parser = ["es-en", "en-es"])]
+    direction: Option<String>,
 
     /// OpenAI API key (or set OPENAI_API_KEY env var)
-    #[arg(long)]
+    #[arg(long, global = true, env = "OPENAI_API_KEY", hide_env_values = true)]
     api_key: Option<String>,
     
     /// Use local Whisper API instead of OpenAI
-    #[arg(long, default_value_t = false)]
+    #[arg(long, global = true, default_value_t = false)]
     local: bool,
 
+    /// ASR backend: openai or local
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_ASR_BACKEND", value_parser = ["openai", "local"])]
+    asr_backend: Option<String>,
+
+    /// Local Whisper endpoint (e.g. http://localhost:8000/transcribe)
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_ASR_URL")]
+    asr_url: Option<String>,
+
     /// Path to Gemma model (GGUF)
-    #[arg(long)]
-    gemma_model: String,
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_MODEL")]
+    gemma_model: Option<String>,
+
+    /// Registered model to use, by name (see `models list`)
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_MODEL_NAME", conflicts_with = "gemma_model")]
+    model: Option<String>,
+
+    /// Context tokens for Gemma [default: 2048]
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_CTX")]
+    gemma_ctx: Option<usize>,
+
+    /// Answer known phrases from a built-in phrasebook when inference fails
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_PHRASEBOOK_FALLBACK")]
+    phrasebook_fallback: bool,
+
+    /// Chat template: auto (from the model file), gemma, llama3, chatml or mistral [default: auto]
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_TEMPLATE")]
+    template: Option<String>,
+
+    /// TOML prompt template used instead of the built-in ones
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_TEMPLATE_FILE")]
+    template_file: Option<String>,
+
+    /// Maximum tokens to generate [default: 256]
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_MAX_TOKENS")]
+    max_tokens: Option<usize>,
+
+    /// Sampling temperature [default: 0.1]
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_TEMPERATURE")]
+    temperature: Option<f32>,
+
+    /// Sample from the K most likely tokens (0 disables)
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_TOP_K", value_name = "K")]
+    top_k: Option<u32>,
+
+    /// Nucleus sampling threshold
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_TOP_P", value_name = "P")]
+    top_p: Option<f32>,
+
+    /// Minimum token probability relative to the most likely token
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_MIN_P", value_name = "P")]
+    min_p: Option<f32>,
+
+    /// Penalty for repeated tokens (1.0 disables)
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_REPEAT_PENALTY", value_name = "X")]
+    repeat_penalty: Option<f32>,
+
+    /// RNG seed for reproducible output
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_SEED")]
+    seed: Option<u32>,
+
+    /// Stop generating at this text; may be repeated
+    #[arg(long = "stop", global = true, value_name = "TEXT")]
+    stop: Vec<String>,
+
+    /// Kill a llama.cpp run after this many seconds [default: 120]
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_INFERENCE_TIMEOUT", value_name = "SECS")]
+    inference_timeout: Option<u64>,
 
-    /// Context tokens for Gemma
-    #[arg(long, default_value_t = 2048)]
-    gemma_ctx: usize,
+    /// Give up on an ASR request after this many seconds [default: 120]
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_ASR_TIMEOUT", value_name = "SECS")]
+    asr_timeout: Option<u64>,
+
+    /// Translation memory (TMX) for exact and fuzzy matches
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_TM", value_name = "TMX")]
+    tm: Option<String>,
 
     /// Run local UI (http://localhost:PORT)
     #[arg(long, default_value_t = false)]
     ui: bool,
 
-    /// UI port
-    #[arg(long, default_value_t = 8080)]
-    port: u16,
+    /// UI bind address [default: 0.0.0.0]
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_BIND")]
+    bind: Option<String>,
+
+    /// UI port [default: 8080]
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_PORT")]
+    port: Option<u16>,
+
+    /// Token for the UI's admin endpoints [default: loopback clients only]
+    #[arg(long, global = true, env = "GEMMA_TRANSLATOR_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
 
     /// Verbose logs
-    #[arg(long, default_value_t = false)]
+    #[arg(long, global = true, default_value_t = false)]
     verbose: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Interactive text translation with the model settings kept between lines
+    Repl,
+    /// Model file tools
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ModelsCommand {
+    /// List registered models with their metadata
+    List,
+    /// Show what a GGUF file contains and whether it fits in memory
+    Inspect {
+        /// GGUF model file
+        file: String,
+        /// Also list every metadata entry and tensor
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+fn resolve_config(args: &Args) -> anyhow::Result<Config> {
+    apply_args(Config::load(args.config.as_deref())?, args)
+}
+
+// Layer CLI flags and env vars (merged by clap) over the config files and defaults.
+fn apply_args(mut cfg: Config, args: &Args) -> anyhow::Result<Config> {
+    if let Some(model) = &args.gemma_model {
+        cfg.gemma.model_path = model.clone();
+        cfg.gemma.model = None;
+    }
+    if let Some(name) = &args.model {
+        cfg.gemma.model = Some(name.clone());
+    }
+    // A registered model brings its own template and context size; the flags below win
+    if let Some(name) = cfg.gemma.model.clone() {
+        models::find(&cfg.models, &name)?.settings.apply(&mut cfg.gemma);
+    }
+    if let Some(n_ctx) = args.gemma_ctx {
+        cfg.gemma.n_ctx = n_ctx;
+    }
+    if args.phrasebook_fallback {
+        cfg.gemma.phrasebook_fallback = true;
+    }
+    if let Some(template) = &args.template {
+        cfg.gemma.template = template.clone();
+    }
+    if let Some(file) = &args.template_file {
+        cfg.gemma.template_file = Some(file.clone());
+    }
+    if let Some(max_tokens) = args.max_tokens {
+        cfg.sampling.max_tokens = max_tokens;
+    }
+    if let Some(temperature) = args.temperature {
+        cfg.sampling.temperature = temperature;
+    }
+    if let Some(top_k) = args.top_k {
+        cfg.sampling.top_k = Some(top_k);
+    }
+    if let Some(top_p) = args.top_p {
+        cfg.sampling.top_p = Some(top_p);
+    }
+    if let Some(min_p) = args.min_p {
+        cfg.sampling.min_p = Some(min_p);
+    }
+    if let Some(penalty) = args.repeat_penalty {
+        cfg.sampling.repeat_penalty = Some(penalty);
+    }
+    if let Some(seed) = args.seed {
+        cfg.sampling.seed = Some(seed);
+    }
+    if !args.stop.is_empty() {
+        cfg.sampling.stop = args.stop.clone();
+    }
+    if let Some(backend) = args.asr_backend.as_deref().and_then(AsrBackend::from_str) {
+        cfg.asr.backend = backend;
+    }
+    if args.local {
+        cfg.asr.backend = AsrBackend::Local;
+    }
+    if let Some(url) = &args.asr_url {
+        cfg.asr.url = Some(url.clone());
+    }
+    if let Some(key) = &args.api_key {
+        cfg.asr.api_key = Some(key.clone());
+    }
+    if let Some(secs) = args.inference_timeout {
+        cfg.timeouts.inference_secs = secs;
+    }
+    if let Some(secs) = args.asr_timeout {
+        cfg.timeouts.asr_secs = secs;
+    }
+    if let Some(path) = &args.tm {
+        cfg.memory.path = Some(path.clone());
+    }
+    if let Some(bind) = &args.bind {
+        cfg.server.bind = bind.clone();
+    }
+    if let Some(port) = args.port {
+        cfg.server.port = port;
+    }
+    if let Some(token) = &args.admin_token {
+        cfg.server.admin_token = Some(token.clone());
+    }
+    if let Some(direction) = &args.direction {
+        cfg.languages.default_direction = direction.clone();
+    }
+
+    cfg.validate()?;
+    Ok(cfg)
+}
+
+// Report an error with its stable code and exit with the matching status.
+fn fail(context: &str, err: &anyhow::Error) -> ! {
+    let code = ErrorCode::of(err);
+    eprintln!("{} [{}]: {}", context, code.as_str(), err);
+    std::process::exit(code.exit_code());
+}
+
+// The first Ctrl-C cancels the running step, killing llama.cpp if needed; a second exits at once.
+// Not used for the UI, where actix handles signals for a graceful shutdown.
+fn install_interrupt_handler() -> CancelToken {
+    let cancel = CancelToken::new();
+    let handler_token = cancel.clone();
+    let installed = ctrlc::set_handler(move || {
+        if handler_token.is_cancelled() {
+            llama_server::stop_all();
+            std::process::exit(ErrorCode::Cancelled.exit_code());
+        }
+        handler_token.cancel();
+    });
+    if let Err(e) = installed {
+        log::warn!("Could not install Ctrl-C handler: {}", e);
+    }
+    cancel
 }
 
 fn main() {
@@ -63,11 +307,71 @@ fn main() {
         .filter_level(if args.verbose { LevelFilter::Debug } else { LevelFilter::Info })
         .init();
 
+    let cfg = resolve_config(&args).unwrap_or_else(|e| {
+        eprintln!("Config error [{}]: {}", ErrorCode::Config.as_str(), e);
+        std::process::exit(ErrorCode::Config.exit_code());
+    });
+    if let Some(Command::Models { command }) = &args.command {
+        let result = match command {
+            ModelsCommand::List => {
+                models::print_list(&cfg.models, &cfg.gemma.model_path);
+                Ok(())
+            }
+            ModelsCommand::Inspect { file, all } => models::print_inspection(std::path::Path::new(file), cfg.gemma.n_ctx, *all),
+        };
+        if let Err(e) = result {
+            eprintln!("Model error [{}]: {}", ErrorCode::ModelUnsupported.as_str(), e);
+            std::process::exit(ErrorCode::ModelUnsupported.exit_code());
+        }
+        return;
+    }
+    // A missing model is reported on first use, so phrasebook-only demos still start
+    if std::path::Path::new(&cfg.gemma.model_path).exists() {
+        if let Err(e) = models::validate(&cfg.gemma_config()) {
+            eprintln!("Model error [{}]: {}", ErrorCode::ModelUnsupported.as_str(), e);
+            std::process::exit(ErrorCode::ModelUnsupported.exit_code());
+        }
+    }
+    // The cache is only an optimization; a broken store shouldn't stop translation
+    if let Err(e) = cache::configure(&cfg.cache) {
+        log::warn!("Translation cache disabled: {}", e);
+        let _ = cache::configure(&config::CacheSection { enabled: false, ..cfg.cache.clone() });
+    }
+    if let Err(e) = memory::configure(&cfg.memory).and_then(|_| glossary::configure(&cfg.glossaries)) {
+        eprintln!("Config error [{}]: {}", ErrorCode::Config.as_str(), e);
+        std::process::exit(ErrorCode::Config.exit_code());
+    }
+    // Resolve the prompt template now so a broken template file fails at startup
+    if let Err(e) = cfg.gemma_config().prompt_template() {
+        eprintln!("Config error [{}]: {}", ErrorCode::Config.as_str(), e);
+        std::process::exit(ErrorCode::Config.exit_code());
+    }
+
+    if let Some(Command::Repl) = args.command {
+        if let Err(e) = repl::run(&cfg, install_interrupt_handler()) {
+            fail("REPL error", &e);
+        }
+        return;
+    }
+
     if args.ui {
         #[cfg(feature = "ui")] {
-            let port = args.port;
-            println!("UI: http://localhost:{port}");
-            actix_web::rt::System::new().block_on(ui::ui::run(port)).unwrap();
+            let (bind, port) = (&cfg.server.bind, cfg.server.port);
+            match bind.parse::<std::net::IpAddr>() {
+                // Listening everywhere; localhost is the one address sure to work here
+                Ok(ip) if ip.is_unspecified() => println!("UI: http://localhost:{} (listening on {} port {})", port, bind, port),
+                Ok(std::net::IpAddr::V6(ip)) => println!("UI: http://[{}]:{}", ip, port),
+                _ => println!("UI: http://{}:{}", bind, port),
+            }
+            if let Err(e) = actix_web::rt::System::new().block_on(ui::ui::run(cfg)) {
+                // The server only refuses to start on its own for a missing model
+                let code = match e.kind() {
+                    std::io::ErrorKind::NotFound => ErrorCode::ModelNotFound,
+                    _ => ErrorCode::Internal,
+                };
+                eprintln!("UI error [{}]: {}", code.as_str(), e);
+                std::process::exit(code.exit_code());
+            }
             return;
         }
         #[cfg(not(feature = "ui"))]
@@ -86,34 +390,23 @@ fn main() {
     
     #[cfg(feature = "realtime")]
     {
-        let dir = Direction::from_str(&args.direction).expect("Invalid direction");
-        
-        // Get API key from args or environment
-        let api_key = args.api_key.or_else(|| std::env::var("OPENAI_API_KEY").ok());
+        let dir = Direction::from_str(&cfg.languages.default_direction).expect("Invalid direction");
         
-        let asr_cfg = AsrConfig { 
-            api_key,
-            use_local: args.local 
-        };
+        let asr = AsrClient::with_runtime(cfg.asr_config()).unwrap_or_else(|e| fail("ASR error", &e));
+        let cancel = install_interrupt_handler();
         let text = if let Some(path) = args.wav.as_ref() {
-            transcribe_wav(path, &asr_cfg).unwrap_or_else(|e| {
-                eprintln!("ASR error: {}", e);
-                std::process::exit(1);
-            })
+            asr.transcribe_wav(path, &cancel).unwrap_or_else(|e| fail("ASR error", &e))
         } else {
             let secs = args.realtime.unwrap_or(5);
-            asr::realtime::record_and_transcribe(&asr_cfg, secs).unwrap_or_else(|e| {
-                eprintln!("Recording error: {}", e);
-                std::process::exit(1);
-            })
+            asr::realtime::record_and_transcribe(&asr, secs, &cancel).unwrap_or_else(|e| fail("Recording error", &e))
         };
 
-        let gemma_cfg = GemmaConfig { model_path: args.gemma_model.clone(), n_ctx: args.gemma_ctx };
-        let translated = translate(&gemma_cfg, dir, &text).unwrap_or_else(|e| {
-            eprintln!("Translation error: {}", e);
-            std::process::exit(1);
-        });
-        println!("{}", translated);
+        let gemma_cfg = cfg.gemma_config();
+        let translated = translate(&gemma_cfg, dir, &text, &cancel).unwrap_or_else(|e| fail("Translation error", &e.into()));
+        if translated.fallback {
+            eprintln!("Note: model inference failed; output is from the phrasebook fallback");
+        }
+        println!("{}", translated.text);
     }
     #[cfg(not(feature = "realtime"))]
     {
@@ -0,0 +1,300 @@
+//! Translation memory: earlier human translations, imported from TMX.
+//!
+//! Exact matches are returned without running the model. Close matches are handed to
+//! the prompt as reference examples so recurring phrasing is translated consistently.
+
+use crate::config::MemorySection;
+use crate::gemma::Direction;
+use crate::xml;
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct MemoryUnit {
+    pub source: String,
+    pub target: String,
+}
+
+/// A fuzzy match and its similarity to the input (0.0 - 1.0).
+#[derive(Debug, Clone)]
+pub struct MemoryMatch {
+    pub unit: MemoryUnit,
+    pub score: f32,
+}
+
+pub enum Lookup {
+    Exact(String),
+    Fuzzy(Vec<MemoryMatch>),
+}
+
+struct TranslationMemory {
+    threshold: f32,
+    max_examples: usize,
+    /// Units per language pair, e.g. `es-en`
+    units: HashMap<String, Vec<MemoryUnit>>,
+}
+
+lazy_static! {
+    static ref MEMORY: Mutex<TranslationMemory> = Mutex::new(TranslationMemory {
+        threshold: MemorySection::default().threshold,
+        max_examples: MemorySection::default().max_examples,
+        units: HashMap::new(),
+    });
+}
+
+/// Apply the `[memory]` settings, importing the configured TMX file if any.
+pub fn configure(section: &MemorySection) -> Result<()> {
+    {
+        let mut memory = MEMORY.lock().unwrap();
+        memory.threshold = section.threshold;
+        memory.max_examples = section.max_examples;
+        memory.units.clear();
+    }
+    if let Some(path) = &section.path {
+        import_tmx(Path::new(path))?;
+    }
+    Ok(())
+}
+
+/// Add every unit of a TMX file, in both directions where both languages are present.
+/// Returns the number of translation units read.
+pub fn import_tmx(path: &Path) -> Result<usize> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read translation memory {}: {}", path.display(), e))?;
+    let units = parse_tmx(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+
+    let mut memory = MEMORY.lock().unwrap();
+    for variants in &units {
+        for (source_lang, source) in variants {
+            for (target_lang, target) in variants {
+                if source_lang == target_lang {
+                    continue;
+                }
+                let pair = format!("{}-{}", source_lang, target_lang);
+                if Direction::from_str(&pair).is_none() {
+                    continue;
+                }
+                memory.units.entry(pair).or_default().push(MemoryUnit {
+                    source: source.clone(),
+                    target: target.clone(),
+                });
+            }
+        }
+    }
+    log::info!("Imported {} translation units from {}", units.len(), path.display());
+    Ok(units.len())
+}
+
+pub fn clear() {
+    MEMORY.lock().unwrap().units.clear();
+}
+
+#[cfg(feature = "ui")]
+pub fn len() -> usize {
+    MEMORY.lock().unwrap().units.values().map(Vec::len).sum()
+}
+
+/// Exact match for `input` if there is one, otherwise up to `max_examples` units at or
+/// above the similarity threshold, best first.
+pub fn lookup(dir: Direction, input: &str) -> Lookup {
+    let memory = MEMORY.lock().unwrap();
+    let Some(units) = memory.units.get(dir.as_str()) else {
+        return Lookup::Fuzzy(Vec::new());
+    };
+
+    let wanted = collapse(input);
+    if let Some(unit) = units.iter().find(|u| collapse(&u.source) == wanted) {
+        return Lookup::Exact(unit.target.clone());
+    }
+
+    let words = tokens(input);
+    let mut matches: Vec<MemoryMatch> = units
+        .iter()
+        .filter_map(|unit| {
+            let candidate = tokens(&unit.source);
+            // The length difference alone is already that many edits
+            if length_bound(&words, &candidate) < memory.threshold {
+                return None;
+            }
+            let score = similarity(&words, &candidate);
+            (score >= memory.threshold).then(|| MemoryMatch { unit: unit.clone(), score })
+        })
+        .collect();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(memory.max_examples);
+    Lookup::Fuzzy(matches)
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Lowercased words without surrounding punctuation
+fn tokens(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+// Upper bound on `similarity` from the lengths alone
+fn length_bound(a: &[String], b: &[String]) -> f32 {
+    let longest = a.len().max(b.len()).max(1);
+    1.0 - a.len().abs_diff(b.len()) as f32 / longest as f32
+}
+
+/// Word-level edit distance turned into a similarity, as CAT tools report fuzzy matches.
+fn similarity(a: &[String], b: &[String]) -> f32 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 0.0;
+    }
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut row = vec![0; b.len() + 1];
+    for (i, wa) in a.iter().enumerate() {
+        row[0] = i + 1;
+        for (j, wb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(wa != wb);
+            row[j + 1] = substitution.min(prev[j + 1] + 1).min(row[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut row);
+    }
+    1.0 - prev[b.len()] as f32 / longest as f32
+}
+
+/// Each `<tu>` as `(language, segment)` pairs. Languages are reduced to their primary
+/// subtag (`es-ES` becomes `es`). Inline markup inside `<seg>` is dropped.
+fn parse_tmx(text: &str) -> Result<Vec<Vec<(String, String)>>> {
+    let mut units = Vec::new();
+    for tu in xml::elements(text, "tu")? {
+        let mut variants = Vec::new();
+        for tuv in xml::elements(tu.content, "tuv")? {
+            let Some(lang) = tuv.lang() else { continue };
+            let Some(seg) = xml::first(tuv.content, "seg")? else { continue };
+            let seg = collapse(&xml::unescape(&strip_tags(seg)));
+            if !seg.is_empty() {
+                variants.push((lang, seg));
+            }
+        }
+        if variants.len() >= 2 {
+            units.push(variants);
+        }
+    }
+    Ok(units)
+}
+
+// Drop inline TMX markup. The content of <bpt>, <ept>, <ph>, <it> and <ut> is native
+// formatting code and goes too; <hi> and <sub> wrap real text, which is kept.
+fn strip_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut depth = 0usize;
+    let mut rest = text;
+    while let Some(lt) = rest.find('<') {
+        if depth == 0 {
+            out.push_str(&rest[..lt]);
+        }
+        let Some(gt) = rest[lt..].find('>') else { break };
+        let tag = &rest[lt + 1..lt + gt];
+        let name = tag.trim_start_matches('/').split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        if matches!(name, "bpt" | "ept" | "ph" | "it" | "ut") && !tag.ends_with('/') {
+            if tag.starts_with('/') {
+                depth = depth.saturating_sub(1);
+            } else {
+                depth += 1;
+            }
+        }
+        rest = &rest[lt + gt + 1..];
+    }
+    if depth == 0 {
+        out.push_str(rest);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tmx version="1.4"><header srclang="en-US"/><body>
+  <tu>
+    <tuv xml:lang="en-US"><seg>Save  the <bpt i="1">&lt;b&gt;</bpt>file<ept i="1">&lt;/b&gt;</ept> now.</seg></tuv>
+    <tuv xml:lang="es-ES"><seg>Guarde el <hi>archivo</hi> ahora.</seg></tuv>
+  </tu>
+  <tu>
+    <tuv lang="en"><seg>Open the settings menu &amp; pick a language.</seg></tuv>
+    <tuv lang="es"><seg>Abra el menú de ajustes y elija un idioma.</seg></tuv>
+    <tuv lang="fr"><seg>Ouvrez le menu des réglages et choisissez une langue.</seg></tuv>
+  </tu>
+  <tu><tuv xml:lang="en"><seg>Only one language</seg></tuv></tu>
+  <tu>
+    <tuv xml:lang="en"><seg>Close the settings menu.</seg></tuv>
+    <tuv xml:lang="es"><seg>Cierre el menú de ajustes.</seg></tuv>
+  </tu>
+</body></tmx>"#;
+
+    #[test]
+    fn parses_tmx_units() {
+        let units = parse_tmx(TMX).unwrap();
+        assert_eq!(units.len(), 3);
+        assert_eq!(
+            units[0],
+            [("en".to_string(), "Save the file now.".to_string()), ("es".to_string(), "Guarde el archivo ahora.".to_string())]
+        );
+        assert_eq!(units[1].len(), 3);
+        assert_eq!(units[1][0].1, "Open the settings menu & pick a language.");
+    }
+
+    #[test]
+    fn strips_inline_markup_and_native_codes() {
+        assert_eq!(strip_tags("a <ph x=\"1\">{\\b}</ph>b <hi>c</hi> <x/>d"), "a b c d");
+        assert_eq!(strip_tags("<it pos=\"begin\">&lt;i&gt;</it>text"), "text");
+    }
+
+    #[test]
+    fn similarity_is_word_level() {
+        assert_eq!(similarity(&tokens("Save the file."), &tokens("save THE file")), 1.0);
+        assert_eq!(similarity(&tokens("save the file now"), &tokens("save the document now")), 0.75);
+        assert_eq!(similarity(&tokens("a b"), &tokens("a b c d")), 0.5);
+        assert_eq!(similarity(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn length_bound_never_undercuts_similarity() {
+        let pairs = [("a b c d", "a b"), ("one two three", "three two one"), ("x", "x y z w v"), ("", "a")];
+        for (a, b) in pairs {
+            let (a, b) = (tokens(a), tokens(b));
+            assert!(length_bound(&a, &b) >= similarity(&a, &b));
+        }
+        assert_eq!(length_bound(&tokens("a b c d"), &tokens("a")), 0.25);
+    }
+
+    // The only test touching the shared memory, so no other test sees its units
+    #[test]
+    fn imports_and_looks_up() {
+        let path = std::env::temp_dir().join(format!("memory-test-{}.tmx", std::process::id()));
+        std::fs::write(&path, TMX).unwrap();
+        let section = MemorySection { path: Some(path.display().to_string()), threshold: 0.6, max_examples: 1 };
+        configure(&section).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // Both directions of the three usable units; French has no direction
+        assert_eq!(MEMORY.lock().unwrap().units.values().map(Vec::len).sum::<usize>(), 6);
+
+        assert!(matches!(lookup(Direction::EnToEs, "Save the  file now."), Lookup::Exact(t) if t == "Guarde el archivo ahora."));
+        assert!(matches!(lookup(Direction::EsToEn, "Cierre el menú de ajustes."), Lookup::Exact(t) if t == "Close the settings menu."));
+        let Lookup::Fuzzy(matches) = lookup(Direction::EnToEs, "Open the settings menu and pick a voice.") else {
+            panic!("expected a fuzzy match");
+        };
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].unit.target, "Abra el menú de ajustes y elija un idioma.");
+        assert_eq!(matches[0].score, 0.75);
+        assert!(matches!(lookup(Direction::EnToEs, "Something else entirely"), Lookup::Fuzzy(m) if m.is_empty()));
+
+        clear();
+        assert!(matches!(lookup(Direction::EnToEs, "Save the file now."), Lookup::Fuzzy(m) if m.is_empty()));
+    }
+}
@@ -0,0 +1,230 @@
+//! Process-wide pipeline metrics, rendered in the Prometheus text format by `/metrics`.
+
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+const LATENCY_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+const TOKENS_PER_SECOND_BUCKETS: &[f64] = &[1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self { bounds, counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+// Without the UI nothing renders the registry, but recording stays unconditional
+#[cfg_attr(not(feature = "ui"), allow(dead_code))]
+#[derive(Default)]
+struct Registry {
+    requests: BTreeMap<(String, String, u16), u64>,
+    translation_seconds: BTreeMap<String, Histogram>,
+    tokens_per_second: Option<Histogram>,
+    generated_tokens: u64,
+    asr_seconds: BTreeMap<String, Histogram>,
+    model_load_seconds: Option<f64>,
+    fallback: BTreeMap<&'static str, u64>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+#[cfg(feature = "ui")]
+pub fn record_request(method: &str, endpoint: &str, status: u16) {
+    let mut reg = REGISTRY.lock().unwrap();
+    *reg.requests.entry((method.to_string(), endpoint.to_string(), status)).or_default() += 1;
+}
+
+/// End-to-end translation latency, labelled by the backend that produced the output.
+pub fn observe_translation(backend: &str, seconds: f64) {
+    let mut reg = REGISTRY.lock().unwrap();
+    reg.translation_seconds
+        .entry(backend.to_string())
+        .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+        .observe(seconds);
+}
+
+/// Decode throughput as reported by llama.cpp's timing summary.
+pub fn observe_generation(tokens: u64, seconds: f64) {
+    let mut reg = REGISTRY.lock().unwrap();
+    reg.generated_tokens += tokens;
+    if seconds > 0.0 {
+        reg.tokens_per_second
+            .get_or_insert_with(|| Histogram::new(TOKENS_PER_SECOND_BUCKETS))
+            .observe(tokens as f64 / seconds);
+    }
+}
+
+#[cfg(feature = "asr")]
+pub fn observe_asr(backend: &str, seconds: f64) {
+    let mut reg = REGISTRY.lock().unwrap();
+    reg.asr_seconds
+        .entry(backend.to_string())
+        .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+        .observe(seconds);
+}
+
+pub fn set_model_load_seconds(seconds: f64) {
+    REGISTRY.lock().unwrap().model_load_seconds = Some(seconds);
+}
+
+/// Count a phrasebook fallback, split by whether the phrase was known.
+pub fn record_fallback(hit: bool) {
+    let mut reg = REGISTRY.lock().unwrap();
+    *reg.fallback.entry(if hit { "hit" } else { "miss" }).or_default() += 1;
+}
+
+#[cfg(feature = "ui")]
+pub fn render(queue_depth: usize, queue_capacity: usize) -> String {
+    let reg = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    header(&mut out, "gemma_http_requests_total", "counter", "HTTP requests by method, endpoint and status");
+    for ((method, endpoint, status), count) in &reg.requests {
+        out.push_str(&format!(
+            "gemma_http_requests_total{{method=\"{}\",endpoint=\"{}\",status=\"{}\"}} {}\n",
+            escape(method),
+            escape(endpoint),
+            status,
+            count
+        ));
+    }
+
+    header(&mut out, "gemma_translation_duration_seconds", "histogram", "Translation latency by backend");
+    for (backend, hist) in &reg.translation_seconds {
+        histogram(&mut out, "gemma_translation_duration_seconds", &format!("backend=\"{}\"", escape(backend)), hist);
+    }
+
+    header(&mut out, "gemma_tokens_per_second", "histogram", "Generation throughput reported by llama.cpp");
+    if let Some(hist) = &reg.tokens_per_second {
+        histogram(&mut out, "gemma_tokens_per_second", "", hist);
+    }
+
+    header(&mut out, "gemma_generated_tokens_total", "counter", "Tokens generated by llama.cpp");
+    out.push_str(&format!("gemma_generated_tokens_total {}\n", reg.generated_tokens));
+
+    header(&mut out, "gemma_asr_duration_seconds", "histogram", "ASR latency by backend");
+    for (backend, hist) in &reg.asr_seconds {
+        histogram(&mut out, "gemma_asr_duration_seconds", &format!("backend=\"{}\"", escape(backend)), hist);
+    }
+
+    header(&mut out, "gemma_job_queue_depth", "gauge", "Jobs waiting for a worker");
+    out.push_str(&format!("gemma_job_queue_depth {}\n", queue_depth));
+    header(&mut out, "gemma_job_queue_capacity", "gauge", "Maximum number of queued jobs");
+    out.push_str(&format!("gemma_job_queue_capacity {}\n", queue_capacity));
+
+    header(&mut out, "gemma_model_load_seconds", "gauge", "Model load time of the last llama.cpp run");
+    if let Some(seconds) = reg.model_load_seconds {
+        out.push_str(&format!("gemma_model_load_seconds {}\n", seconds));
+    }
+
+    header(&mut out, "gemma_fallback_total", "counter", "Phrasebook fallbacks, by whether the phrase was known");
+    for (result, count) in &reg.fallback {
+        out.push_str(&format!("gemma_fallback_total{{result=\"{}\"}} {}\n", result, count));
+    }
+
+    out
+}
+
+#[cfg(feature = "ui")]
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+}
+
+#[cfg(feature = "ui")]
+fn histogram(out: &mut String, name: &str, labels: &str, hist: &Histogram) {
+    let sep = if labels.is_empty() { "" } else { "," };
+    for (bound, count) in hist.bounds.iter().zip(&hist.counts) {
+        out.push_str(&format!("{}_bucket{{{}{}le=\"{}\"}} {}\n", name, labels, sep, bound, count));
+    }
+    out.push_str(&format!("{}_bucket{{{}{}le=\"+Inf\"}} {}\n", name, labels, sep, hist.count));
+    let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+    out.push_str(&format!("{}_sum{} {}\n", name, labels, hist.sum));
+    out.push_str(&format!("{}_count{} {}\n", name, labels, hist.count));
+}
+
+#[cfg(feature = "ui")]
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(all(test, feature = "ui"))]
+mod tests {
+    use super::*;
+
+    fn observed(values: &[f64]) -> Histogram {
+        let mut hist = Histogram::new(&[1.0, 5.0]);
+        for &value in values {
+            hist.observe(value);
+        }
+        hist
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut out = String::new();
+        histogram(&mut out, "latency", "backend=\"llama\"", &observed(&[0.5, 1.0, 3.0, 10.0]));
+        assert_eq!(
+            out,
+            "latency_bucket{backend=\"llama\",le=\"1\"} 2\n\
+             latency_bucket{backend=\"llama\",le=\"5\"} 3\n\
+             latency_bucket{backend=\"llama\",le=\"+Inf\"} 4\n\
+             latency_sum{backend=\"llama\"} 14.5\n\
+             latency_count{backend=\"llama\"} 4\n"
+        );
+    }
+
+    #[test]
+    fn unlabelled_histograms_have_bare_sum_and_count() {
+        let mut out = String::new();
+        histogram(&mut out, "tps", "", &observed(&[7.0]));
+        assert_eq!(out, "tps_bucket{le=\"1\"} 0\ntps_bucket{le=\"5\"} 0\ntps_bucket{le=\"+Inf\"} 1\ntps_sum 7\ntps_count 1\n");
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    // The only test touching the shared registry
+    #[test]
+    fn renders_valid_exposition_text() {
+        record_request("GET", "/odd\"path\\", 200);
+        observe_generation(20, 2.0);
+        let text = render(1, 8);
+
+        assert!(text.contains("gemma_http_requests_total{method=\"GET\",endpoint=\"/odd\\\"path\\\\\",status=\"200\"} 1\n"), "{}", text);
+        assert!(text.contains("\ngemma_job_queue_depth 1\n") && text.contains("\ngemma_job_queue_capacity 8\n"));
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                assert!(comment.starts_with("HELP gemma_") || comment.starts_with("TYPE gemma_"), "{}", line);
+                continue;
+            }
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            assert!(value.parse::<f64>().is_ok(), "{}", line);
+            assert!(series.starts_with("gemma_") && series.matches('{').count() == series.matches('}').count(), "{}", line);
+        }
+        // Every histogram ends its buckets with +Inf, equal to its count
+        let inf = text.lines().find(|l| l.starts_with("gemma_tokens_per_second_bucket{le=\"+Inf\"}")).unwrap();
+        let count = text.lines().find(|l| l.starts_with("gemma_tokens_per_second_count ")).unwrap();
+        assert_eq!(inf.rsplit_once(' ').unwrap().1, count.rsplit_once(' ').unwrap().1);
+    }
+}
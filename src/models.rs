@@ -0,0 +1,433 @@
+//! Model inspection and validation: what a GGUF file contains, whether it is an
+//! instruction-tuned model the translator can prompt, and whether it fits in memory.
+//! Also the registry of named models and, for the server, the model currently in use.
+
+use crate::config::{ModelSettings, ModelsSection};
+use crate::gemma::GemmaConfig;
+use crate::gguf::{self, Metadata};
+use crate::template;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "ui")]
+use std::sync::{Arc, RwLock};
+use sysinfo::{MemoryRefreshKind, RefreshKind, System};
+
+/// Room for llama.cpp's compute buffers and runtime on top of weights and KV cache
+const RUNTIME_OVERHEAD_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Words in a model's name, fine-tune or file name that mark an instruction-tuned build
+const INSTRUCT_WORDS: &[&str] = &["it", "instruct", "chat"];
+
+/// Summary of a GGUF model file.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelInfo {
+    pub path: String,
+    pub name: Option<String>,
+    pub architecture: Option<String>,
+    pub gguf_version: u32,
+    pub file_bytes: u64,
+    pub parameters: u64,
+    pub quantization: Option<&'static str>,
+    /// Context length the model was trained for
+    pub context_length: Option<u64>,
+    pub tokenizer: Option<String>,
+    pub vocab_size: Option<u64>,
+    pub has_chat_template: bool,
+    /// Built-in prompt template family that matches, if any
+    pub template_family: Option<&'static str>,
+    /// Whether the name, fine-tune or file name says instruction-tuned
+    pub instruct: bool,
+    pub tensors: usize,
+    #[serde(skip)]
+    meta: Metadata,
+}
+
+impl ModelInfo {
+    /// Read the header of the GGUF file at `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let file_bytes = std::fs::metadata(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?
+            .len();
+        let meta = gguf::read_metadata(path)?;
+        let (tokenizer, vocab_size) = meta.tokenizer();
+        let file_name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+        // Not the chat template: base models often ship one too
+        let finetune = meta.entries.get("general.finetune").and_then(gguf::Value::as_str);
+        let names = [meta.name(), finetune].map(|name| name.unwrap_or_default().to_lowercase());
+        let instruct = names
+            .iter()
+            .chain([&file_name])
+            .any(|name| name.split(|c: char| !c.is_alphanumeric()).any(|word| INSTRUCT_WORDS.contains(&word)));
+        Ok(Self {
+            path: path.display().to_string(),
+            name: meta.name().map(str::to_string),
+            architecture: meta.architecture().map(str::to_string),
+            gguf_version: meta.version,
+            file_bytes,
+            parameters: meta.parameter_count(),
+            quantization: meta.quantization(),
+            context_length: meta.hparam("context_length"),
+            tokenizer: tokenizer.map(str::to_string),
+            vocab_size,
+            has_chat_template: meta.chat_template().is_some(),
+            template_family: template::family(&meta).map(|f| f.as_str()),
+            instruct,
+            tensors: meta.tensors.len(),
+            meta,
+        })
+    }
+
+    /// Rough memory needed to run with `n_ctx` tokens of context: the weights (mapped
+    /// whole), an f16 KV cache and a fixed allowance for compute buffers.
+    pub fn estimate_ram_bytes(&self, n_ctx: usize) -> u64 {
+        let n_ctx = self.context_length.map_or(n_ctx as u64, |max| max.min(n_ctx as u64));
+        let layers = self.meta.hparam("block_count").unwrap_or(0);
+        let heads = self.meta.hparam("attention.head_count").unwrap_or(1).max(1);
+        let kv_heads = self.meta.hparam("attention.head_count_kv").unwrap_or(heads);
+        let head_dim = self
+            .meta
+            .hparam("attention.key_length")
+            .unwrap_or_else(|| self.meta.hparam("embedding_length").unwrap_or(0) / heads);
+        // Keys and values, two bytes each
+        let kv_cache = 2 * layers * n_ctx * kv_heads * head_dim * 2;
+        self.file_bytes + kv_cache + RUNTIME_OVERHEAD_BYTES
+    }
+
+    /// Every metadata entry, for `models inspect --all`.
+    pub fn metadata(&self) -> impl Iterator<Item = (&String, &gguf::Value)> {
+        self.meta.entries.iter()
+    }
+
+    pub fn tensors(&self) -> &[gguf::Tensor] {
+        &self.meta.tensors
+    }
+}
+
+/// Memory the system can still hand out, and its total, in bytes.
+pub fn system_memory() -> (u64, u64) {
+    let sys = System::new_with_specifics(RefreshKind::new().with_memory(MemoryRefreshKind::new().with_ram()));
+    (sys.available_memory(), sys.total_memory())
+}
+
+/// Check that the configured model can be used for translation: a readable GGUF with
+/// weights, instruction-tuned, and with a prompt template. A model whose template is
+/// set explicitly only needs to be readable. Warns when it may not fit in memory.
+pub fn validate(cfg: &GemmaConfig) -> Result<ModelInfo> {
+    let info = ModelInfo::read(Path::new(&cfg.model_path))
+        .map_err(|e| anyhow!("Not a usable GGUF model: {}", e))?;
+    if info.tensors == 0 {
+        return Err(anyhow!("{} has no weights (a vocabulary-only GGUF?)", cfg.model_path));
+    }
+    let explicit = cfg.template_file.is_some() || cfg.template != "auto";
+    if !explicit {
+        if info.template_family.is_none() {
+            return Err(anyhow!(
+                "{}: no prompt template for architecture {}; set gemma.template or gemma.template_file",
+                cfg.model_path,
+                info.architecture.as_deref().unwrap_or("unknown")
+            ));
+        }
+        if !info.instruct && !info.has_chat_template {
+            return Err(anyhow!(
+                "{} looks like a base model (no chat template, no -it/Instruct in its name); use an instruction-tuned build",
+                cfg.model_path
+            ));
+        }
+        if !info.instruct {
+            log::warn!(
+                "{} has a chat template but no -it/Instruct in its name; if it is a base model, translations will be poor",
+                cfg.model_path
+            );
+        }
+    }
+
+    let needed = info.estimate_ram_bytes(cfg.n_ctx);
+    let (available, _) = system_memory();
+    if available > 0 && needed > available {
+        log::warn!(
+            "{} needs about {} with n_ctx {}, but only {} is available",
+            cfg.model_path,
+            format_bytes(needed),
+            cfg.n_ctx,
+            format_bytes(available)
+        );
+    }
+    Ok(info)
+}
+
+/// Print what `models inspect` reports about the file at `path`.
+pub fn print_inspection(path: &Path, n_ctx: usize, all: bool) -> Result<()> {
+    let info = ModelInfo::read(path)?;
+    let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+
+    println!("File:           {} ({}, GGUF v{})", info.path, format_bytes(info.file_bytes), info.gguf_version);
+    println!("Name:           {}", or_unknown(info.name.clone()));
+    println!("Architecture:   {}", or_unknown(info.architecture.clone()));
+    println!("Parameters:     {} in {} tensors", format_count(info.parameters), info.tensors);
+    println!("Quantization:   {}", or_unknown(info.quantization.map(str::to_string)));
+    println!("Context length: {}", or_unknown(info.context_length.map(|n| n.to_string())));
+    println!(
+        "Tokenizer:      {}{}",
+        or_unknown(info.tokenizer.clone()),
+        info.vocab_size.map(|n| format!(", {} tokens", n)).unwrap_or_default()
+    );
+    println!(
+        "Chat template:  {}",
+        match (info.has_chat_template, info.template_family) {
+            (true, Some(family)) => format!("yes ({})", family),
+            (true, None) => "yes (no built-in equivalent)".to_string(),
+            (false, Some(family)) => format!("no ({} by architecture)", family),
+            (false, None) => "no".to_string(),
+        }
+    );
+    println!(
+        "Instruct:       {}",
+        match (info.instruct, info.has_chat_template) {
+            (true, _) => "yes",
+            (false, true) => "unclear (chat template, but no -it/Instruct in its name)",
+            (false, false) => "no (base model?)",
+        }
+    );
+
+    let needed = info.estimate_ram_bytes(n_ctx);
+    let (available, total) = system_memory();
+    println!(
+        "Memory:         ~{} with n_ctx {}; {} of {} available{}",
+        format_bytes(needed),
+        n_ctx,
+        format_bytes(available),
+        format_bytes(total),
+        if needed > available { " (does not fit)" } else { "" }
+    );
+
+    if all {
+        println!();
+        for (key, value) in info.metadata() {
+            // The chat template is long and already summarized above
+            if key == "tokenizer.chat_template" {
+                continue;
+            }
+            println!("{} = {}", key, value);
+        }
+        println!();
+        for tensor in info.tensors() {
+            println!("{} {:?} {}", tensor.name, tensor.dims, tensor.type_name().unwrap_or("unknown"));
+        }
+    }
+    Ok(())
+}
+
+/// A model file known by name, from the models directory or `[models.named]`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RegisteredModel {
+    pub name: String,
+    /// File, and for configured models any template and context size of their own
+    #[serde(flatten)]
+    pub settings: ModelSettings,
+    /// Named in the config rather than found by scanning
+    pub configured: bool,
+}
+
+/// Every registered model, sorted by name. A missing models directory just means no
+/// scanned models.
+pub fn registry(section: &ModelsSection) -> Vec<RegisteredModel> {
+    let mut models = BTreeMap::new();
+    if let Ok(entries) = std::fs::read_dir(&section.dir) {
+        for path in entries.flatten().map(|e| e.path()) {
+            let is_gguf = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gguf"));
+            let Some(name) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else { continue };
+            if is_gguf && path.is_file() {
+                let settings = ModelSettings { path: path.display().to_string(), ..ModelSettings::default() };
+                models.insert(name.clone(), RegisteredModel { name, settings, configured: false });
+            }
+        }
+    }
+    for (name, entry) in &section.named {
+        models.insert(name.clone(), RegisteredModel { name: name.clone(), settings: entry.settings(), configured: true });
+    }
+    models.into_values().collect()
+}
+
+/// Look up a registered model by name.
+pub fn find(section: &ModelsSection, name: &str) -> Result<RegisteredModel> {
+    let models = registry(section);
+    if let Some(model) = models.iter().find(|m| m.name == name) {
+        return Ok(model.clone());
+    }
+    let known: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+    Err(anyhow!(
+        "Unknown model {:?}; registered: {}",
+        name,
+        if known.is_empty() { format!("none (no .gguf files in {})", section.dir) } else { known.join(", ") }
+    ))
+}
+
+/// Registered name of the model file at `path`, if it has one.
+#[cfg(feature = "ui")]
+pub fn name_of(section: &ModelsSection, path: &str) -> Option<String> {
+    let target = canonical(path);
+    registry(section).into_iter().find(|m| canonical(&m.settings.path) == target).map(|m| m.name)
+}
+
+fn canonical(path: &str) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+/// Print what `models list` reports: one row per registered model, the active one
+/// marked with `*`.
+pub fn print_list(section: &ModelsSection, active_path: &str) {
+    let models = registry(section);
+    if models.is_empty() {
+        println!("No models registered: no .gguf files in {} and no [models.named] entries", section.dir);
+        return;
+    }
+    let active = canonical(active_path);
+    let width = models.iter().map(|m| m.name.len()).max().unwrap_or(0).max(4);
+    println!("  {:width$}  {:>9}  {:10}  {:>7}  {:7}  PATH", "NAME", "SIZE", "ARCH", "PARAMS", "QUANT");
+    for model in &models {
+        let mark = if canonical(&model.settings.path) == active { "*" } else { " " };
+        match ModelInfo::read(Path::new(&model.settings.path)) {
+            Ok(info) => println!(
+                "{} {:width$}  {:>9}  {:10}  {:>7}  {:7}  {}",
+                mark,
+                model.name,
+                format_bytes(info.file_bytes),
+                info.architecture.as_deref().unwrap_or("unknown"),
+                format_count(info.parameters),
+                info.quantization.unwrap_or("unknown"),
+                model.settings.path
+            ),
+            Err(e) => println!("{} {:width$}  {}  ({})", mark, model.name, model.settings.path, e),
+        }
+    }
+}
+
+/// A model the server is translating with. Requests hold it for as long as they run,
+/// so a replaced model is only released once the last of them has finished.
+#[cfg(feature = "ui")]
+pub struct LoadedModel {
+    /// Registered name, if the file has one
+    pub name: Option<String>,
+    pub gemma: GemmaConfig,
+    pub size_mb: u64,
+}
+
+#[cfg(feature = "ui")]
+impl Drop for LoadedModel {
+    fn drop(&mut self) {
+        log::info!("Unloaded model {}", self.gemma.model_path);
+    }
+}
+
+/// The model new requests use, switchable at runtime.
+#[cfg(feature = "ui")]
+pub struct ActiveModel {
+    current: RwLock<Arc<LoadedModel>>,
+}
+
+#[cfg(feature = "ui")]
+impl ActiveModel {
+    pub fn new(model: LoadedModel) -> Self {
+        Self { current: RwLock::new(Arc::new(model)) }
+    }
+
+    /// The model to use for one request, held until the request is done.
+    pub fn get(&self) -> Arc<LoadedModel> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Make `model` the one new requests use and return the previous one, which
+    /// requests already running keep using.
+    pub fn switch(&self, model: LoadedModel) -> Arc<LoadedModel> {
+        std::mem::replace(&mut *self.current.write().unwrap(), Arc::new(model))
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+    if bytes as f64 >= GIB {
+        format!("{:.1} GiB", bytes as f64 / GIB)
+    } else {
+        format!("{} MiB", bytes / (1024 * 1024))
+    }
+}
+
+// 2506172416 -> "2.51B"
+fn format_count(n: u64) -> String {
+    match n {
+        n if n >= 1_000_000_000 => format!("{:.2}B", n as f64 / 1e9),
+        n if n >= 1_000_000 => format!("{:.0}M", n as f64 / 1e6),
+        n => n.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModelEntry;
+
+    fn models_dir(name: &str, files: &[&str]) -> ModelsSection {
+        let dir = std::env::temp_dir().join(format!("models-test-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            std::fs::write(dir.join(file), b"GGUF").unwrap();
+        }
+        ModelsSection { dir: dir.display().to_string(), named: BTreeMap::new() }
+    }
+
+    #[test]
+    fn named_entries_win_over_scanned_files() {
+        let mut section = models_dir("registry", &["small.gguf", "Other.GGUF", "notes.txt"]);
+        let settings = ModelSettings { path: "/data/small-it.gguf".to_string(), n_ctx: Some(4096), ..ModelSettings::default() };
+        section.named.insert("small".to_string(), ModelEntry::Settings(settings));
+        section.named.insert("big".to_string(), ModelEntry::Path("/data/big-it.gguf".to_string()));
+
+        let models = registry(&section);
+        let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["Other", "big", "small"]);
+        let small = find(&section, "small").unwrap();
+        assert!(small.configured);
+        assert_eq!((small.settings.path.as_str(), small.settings.n_ctx), ("/data/small-it.gguf", Some(4096)));
+        let other = find(&section, "Other").unwrap();
+        assert!(!other.configured);
+        assert!(other.settings.path.ends_with("Other.GGUF"));
+
+        std::fs::remove_dir_all(&section.dir).unwrap();
+    }
+
+    #[test]
+    fn find_rejects_unknown_names() {
+        let mut section = models_dir("find", &[]);
+        let err = find(&section, "gemma").unwrap_err().to_string();
+        assert_eq!(err, format!("Unknown model \"gemma\"; registered: none (no .gguf files in {})", section.dir));
+
+        section.named.insert("gemma-9b".to_string(), ModelEntry::Path("/data/gemma-9b.gguf".to_string()));
+        section.named.insert("qwen".to_string(), ModelEntry::Path("/data/qwen.gguf".to_string()));
+        let err = find(&section, "gemma").unwrap_err().to_string();
+        assert_eq!(err, "Unknown model \"gemma\"; registered: gemma-9b, qwen");
+        std::fs::remove_dir_all(&section.dir).unwrap();
+    }
+
+    #[cfg(feature = "ui")]
+    #[test]
+    fn requests_keep_their_model_across_a_switch() {
+        let loaded = |path: &str| {
+            let mut gemma = crate::config::Config::default().gemma_config();
+            gemma.model_path = path.to_string();
+            LoadedModel { name: None, gemma, size_mb: 0 }
+        };
+        let active = ActiveModel::new(loaded("old.gguf"));
+        let in_flight = active.get();
+
+        let previous = active.switch(loaded("new.gguf"));
+        assert!(Arc::ptr_eq(&previous, &in_flight));
+        assert_eq!(active.get().gemma.model_path, "new.gguf");
+
+        // The switch gives up its reference; the request's is the last one left
+        drop(previous);
+        assert_eq!(Arc::strong_count(&in_flight), 1);
+        assert_eq!(in_flight.gemma.model_path, "old.gguf");
+    }
+}
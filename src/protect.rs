@@ -0,0 +1,277 @@
+//! Masking of text the model must not touch: placeholders and format specifiers, URLs,
+//! email addresses, inline code, markup tags and spans marked `<dnt>...</dnt>`.
+//!
+//! Each span is replaced by a numbered marker such as `⟦0⟧` before prompting and put
+//! back afterwards. A translation that drops or duplicates a marker is rejected.
+
+/// Opening and closing brackets of a marker. Rare enough in real text that the model
+/// copies them through rather than translating them.
+const OPEN: char = '⟦';
+const CLOSE: char = '⟧';
+
+/// Wrapper for spans the user wants left as they are; removed on restore.
+const DNT_OPEN: &str = "<dnt>";
+const DNT_CLOSE: &str = "</dnt>";
+
+/// Input with its protected spans replaced by markers.
+#[derive(Debug, Clone)]
+pub struct Masked {
+    pub text: String,
+    /// Text each marker is restored to, indexed by marker number
+    pub spans: Vec<String>,
+    // Input text each marker replaced, which differs for `<dnt>` spans
+    originals: Vec<String>,
+}
+
+impl Masked {
+    /// `input` as it is, with nothing protected.
+    pub fn unprotected(input: &str) -> Self {
+        Masked { text: input.to_string(), spans: Vec::new(), originals: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Put the original spans back into `output`. Fails with the indices of markers
+    /// that are missing or repeated, so the caller can retry or give up.
+    pub fn restore(&self, output: &str) -> Result<String, Vec<usize>> {
+        let lost: Vec<usize> = (0..self.spans.len())
+            .filter(|&i| output.matches(marker(i).as_str()).count() != 1)
+            .collect();
+        if !lost.is_empty() {
+            return Err(lost);
+        }
+
+        let mut restored = output.to_string();
+        for (i, span) in self.spans.iter().enumerate() {
+            restored = restored.replacen(&marker(i), span, 1);
+        }
+        Ok(restored)
+    }
+
+    /// Undo the masking of part of `self.text`, giving back that part of the input.
+    pub fn reveal(&self, part: &str) -> String {
+        let mut text = part.to_string();
+        for (i, original) in self.originals.iter().enumerate() {
+            text = text.replacen(&marker(i), original, 1);
+        }
+        text
+    }
+}
+
+pub fn marker(index: usize) -> String {
+    format!("{}{}{}", OPEN, index, CLOSE)
+}
+
+/// Replace every protected span in `input` with a marker. Input that already contains
+/// marker brackets is left alone, since its markers could not be told apart.
+pub fn mask(input: &str) -> Masked {
+    if input.contains([OPEN, CLOSE]) {
+        return Masked::unprotected(input);
+    }
+    let mut text = String::with_capacity(input.len());
+    let mut spans = Vec::new();
+    let mut originals = Vec::new();
+    let mut at = 0;
+
+    while at < input.len() {
+        if let Some(end) = input[at..].strip_prefix(DNT_OPEN).and_then(|rest| rest.find(DNT_CLOSE)) {
+            let inner = &input[at + DNT_OPEN.len()..at + DNT_OPEN.len() + end];
+            let len = DNT_OPEN.len() + end + DNT_CLOSE.len();
+            text.push_str(&marker(spans.len()));
+            spans.push(inner.to_string());
+            originals.push(input[at..at + len].to_string());
+            at += len;
+            continue;
+        }
+        let word_start = !input[..at].chars().next_back().is_some_and(char::is_alphanumeric);
+        let matched = code(&input[at..])
+            .or_else(|| tag(&input[at..]))
+            .or_else(|| url(&input[at..]).filter(|_| word_start))
+            .or_else(|| email(&input[at..]).filter(|_| word_start))
+            .or_else(|| placeholder(&input[at..]))
+            .or_else(|| format_spec(&input[at..]));
+        match matched {
+            Some(len) => {
+                text.push_str(&marker(spans.len()));
+                spans.push(input[at..at + len].to_string());
+                originals.push(input[at..at + len].to_string());
+                at += len;
+            }
+            None => {
+                let c = input[at..].chars().next().unwrap_or_default();
+                text.push(c);
+                at += c.len_utf8();
+            }
+        }
+    }
+    Masked { text, spans, originals }
+}
+
+// `inline code` and ```fenced blocks```
+fn code(text: &str) -> Option<usize> {
+    let fence = if text.starts_with("```") { "```" } else { "`" };
+    let rest = text.strip_prefix(fence)?;
+    let end = rest.find(fence)?;
+    (fence.len() > 1 || (end > 0 && !rest[..end].contains('\n'))).then_some(fence.len() + end + fence.len())
+}
+
+// <b>, </a>, <br/>, <img src="..."> and <!-- comments -->, on one line
+fn tag(text: &str) -> Option<usize> {
+    let rest = text.strip_prefix('<')?;
+    if let Some(comment) = rest.strip_prefix("!--") {
+        return comment.find("-->").map(|end| 4 + end + 3);
+    }
+    let first = rest.trim_start_matches('/').chars().next()?;
+    if !first.is_ascii_alphabetic() {
+        return None;
+    }
+    let end = rest.find(['>', '<', '\n'])?;
+    (rest.as_bytes()[end] == b'>').then_some(1 + end + 1)
+}
+
+fn url(text: &str) -> Option<usize> {
+    let prefix = ["https://", "http://", "ftp://", "mailto:", "www."]
+        .into_iter()
+        .find(|p| text.len() >= p.len() && text.as_bytes()[..p.len()].eq_ignore_ascii_case(p.as_bytes()))?;
+    let len = text.find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"')).unwrap_or(text.len());
+    // Sentence punctuation after a URL is not part of it
+    let len = text[..len].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '\'']).len();
+    (len > prefix.len()).then_some(len)
+}
+
+fn email(text: &str) -> Option<usize> {
+    let local = text.find(|c: char| !(c.is_ascii_alphanumeric() || "._%+-".contains(c))).unwrap_or(text.len());
+    if local == 0 || !text[local..].starts_with('@') {
+        return None;
+    }
+    let domain = &text[local + 1..];
+    let len = domain.find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-')).unwrap_or(domain.len());
+    let domain = domain[..len].trim_end_matches(['.', '-']);
+    let dot = domain.rfind('.')?;
+    (dot > 0 && dot + 1 < domain.len()).then_some(local + 1 + domain.len())
+}
+
+// {name}, {0}, {{var}}, ${var}, ICU {count, plural, ...} and %(name)s, on one line
+fn placeholder(text: &str) -> Option<usize> {
+    if let Some(rest) = text.strip_prefix("%(") {
+        let end = rest.find(')')?;
+        let name = &rest[..end];
+        let kind = rest[end + 1..].chars().next()?;
+        let valid = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        return (valid && kind.is_ascii_alphabetic()).then_some(2 + end + 1 + 1);
+    }
+    let start = usize::from(text.starts_with("${"));
+    if !text[start..].starts_with('{') {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, c) in text[start..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(start + i + 1);
+                }
+            }
+            '\n' => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+// printf-style specifiers: %s, %d, %1$s, %-5.2f, %lld, %@, %%. A space flag is not
+// accepted, so "50% of" stays text.
+fn format_spec(text: &str) -> Option<usize> {
+    let rest = text.strip_prefix('%')?;
+    if rest.starts_with('%') {
+        return Some(2);
+    }
+    let mut len = 0;
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    // Positional argument, e.g. %1$s
+    let position = digits(rest);
+    if position > 0 && rest[position..].starts_with('$') {
+        len = position + 1;
+    }
+    len += rest[len..].find(|c: char| !"-+0#".contains(c)).unwrap_or(rest.len() - len);
+    len += digits(&rest[len..]);
+    if rest[len..].starts_with('.') {
+        len += 1 + digits(&rest[len + 1..]);
+    }
+    for modifier in ["hh", "ll", "h", "l", "L", "z", "j", "t", "q"] {
+        if rest[len..].starts_with(modifier) {
+            len += modifier.len();
+            break;
+        }
+    }
+    let conversion = rest[len..].chars().next()?;
+    if !"diouxXeEfFgGaAcspn@".contains(conversion) {
+        return None;
+    }
+    // "%so" or "%dagger" are words glued to a percent sign, not specifiers
+    let after = rest[len + 1..].chars().next();
+    (!after.is_some_and(|c| c.is_alphanumeric())).then_some(1 + len + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_and_restores_protected_spans() {
+        let input = "Hi {name}, see https://example.com/a. Mail bob@example.org, run `ls -l` <b>now</b>: %1$s";
+        let masked = mask(input);
+        assert_eq!(masked.text, "Hi ⟦0⟧, see ⟦1⟧. Mail ⟦2⟧, run ⟦3⟧ ⟦4⟧now⟦5⟧: ⟦6⟧");
+        assert_eq!(masked.spans, ["{name}", "https://example.com/a", "bob@example.org", "`ls -l`", "<b>", "</b>", "%1$s"]);
+        assert_eq!(masked.reveal(&masked.text), input);
+
+        let output = "Hola ⟦0⟧, mira ⟦1⟧. Escribe a ⟦2⟧, ejecuta ⟦3⟧ ⟦4⟧ya⟦5⟧: ⟦6⟧";
+        assert_eq!(
+            masked.restore(output).unwrap(),
+            "Hola {name}, mira https://example.com/a. Escribe a bob@example.org, ejecuta `ls -l` <b>ya</b>: %1$s"
+        );
+    }
+
+    #[test]
+    fn markers_may_move() {
+        let masked = mask("From {start} to {end}");
+        assert_eq!(masked.restore("Hasta ⟦1⟧ desde ⟦0⟧").unwrap(), "Hasta {end} desde {start}");
+    }
+
+    #[test]
+    fn dnt_spans_lose_their_wrapper() {
+        let masked = mask("Use <dnt>Save As</dnt> here");
+        assert_eq!(masked.text, "Use ⟦0⟧ here");
+        assert_eq!(masked.restore("Usa ⟦0⟧ aquí").unwrap(), "Usa Save As aquí");
+        assert_eq!(masked.reveal("Use ⟦0⟧"), "Use <dnt>Save As</dnt>");
+    }
+
+    #[test]
+    fn rejects_missing_and_duplicate_markers() {
+        let masked = mask("{a} and {b} and {c}");
+        assert_eq!(masked.restore("⟦0⟧ y ⟦2⟧"), Err(vec![1]));
+        assert_eq!(masked.restore("⟦0⟧ ⟦0⟧ y ⟦1⟧ y ⟦2⟧"), Err(vec![0]));
+        assert_eq!(masked.restore("nada"), Err(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn leaves_plain_text_alone() {
+        for text in ["50% of the time", "a < b > c", "%so what", "user@localhost", "{unclosed"] {
+            let masked = mask(text);
+            assert!(masked.is_empty(), "{}", text);
+            assert_eq!(masked.text, text);
+        }
+        assert_eq!(mask("%% and %-5.2f").spans, ["%%", "%-5.2f"]);
+    }
+
+    #[test]
+    fn input_with_marker_brackets_is_not_masked() {
+        let masked = mask("⟦0⟧ {name}");
+        assert!(masked.is_empty());
+        assert_eq!(masked.restore("anything").unwrap(), "anything");
+    }
+}
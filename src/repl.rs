@@ -0,0 +1,339 @@
+use crate::cancel::CancelToken;
+use crate::config::Config;
+use crate::gemma::{translate_with_glossary, Direction, GemmaConfig, TranslateError};
+use crate::llama_server::LlamaServer;
+use crate::glossary::Glossary;
+use crate::memory;
+use anyhow::{anyhow, Result};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const HELP: &str = "\
+Type text to translate it. Commands:
+  :dir <es-en|en-es>        switch direction
+  :temp <value>             set sampling temperature
+  :glossary load <file>     load a CSV or TBX glossary (reloaded on :dir)
+  :glossary clear           drop the loaded glossary
+  :tm load <file.tmx>       add a TMX translation memory
+  :tm clear                 drop all translation memory units
+  :save <file.jsonl>        write this session's translations as JSON lines
+  :help                     show this help
+  :quit                     exit (or Ctrl-D)";
+
+#[derive(Serialize)]
+struct Exchange {
+    timestamp: u64,
+    direction: &'static str,
+    input: String,
+    output: String,
+    fallback: bool,
+}
+
+struct Session {
+    gemma: GemmaConfig,
+    direction: Direction,
+    pairs: Vec<String>,
+    glossary: Option<Glossary>,
+    /// File `glossary` came from, reloaded when the direction changes
+    glossary_path: Option<PathBuf>,
+    history: Vec<Exchange>,
+    cancel: CancelToken,
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Interactive translation loop. Settings persist across lines, and the model stays
+/// loaded in a `llama-server` for the whole session where one is installed; without
+/// it every line starts the llama.cpp CLI. `cancel` is set by Ctrl-C while a line is
+/// being translated.
+pub fn run(cfg: &Config, cancel: CancelToken) -> Result<()> {
+    let mut gemma = cfg.gemma_config();
+    if !Path::new(&gemma.model_path).exists() {
+        return Err(anyhow!("Gemma model not found at: {}", gemma.model_path));
+    }
+    match LlamaServer::start(&gemma, &cancel) {
+        Ok(server) => gemma.server = Some(Arc::new(server)),
+        Err(TranslateError::Cancelled) => return Ok(()),
+        Err(e) => log::warn!("Model not kept loaded, starting llama.cpp for every line: {}", e),
+    }
+    let direction = Direction::from_str(&cfg.languages.default_direction)
+        .ok_or_else(|| anyhow!("Invalid direction: {}", cfg.languages.default_direction))?;
+
+    let mut session = Session {
+        gemma,
+        direction,
+        pairs: cfg.languages.pairs.clone(),
+        glossary: None,
+        glossary_path: None,
+        history: Vec::new(),
+        cancel,
+    };
+
+    let mut editor = DefaultEditor::new()?;
+    let history_file = history_path();
+    if let Some(path) = &history_file {
+        let _ = editor.load_history(path);
+    }
+
+    println!("Gemma translator REPL ({}). Type :help for commands.", session.gemma.model_path);
+
+    loop {
+        let prompt = format!("{}> ", session.direction.as_str());
+        match editor.readline(&prompt) {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let _ = editor.add_history_entry(line);
+
+                if let Some(command) = line.strip_prefix(':') {
+                    match session.command(command) {
+                        Ok(Flow::Continue) => {}
+                        Ok(Flow::Quit) => break,
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
+                    continue;
+                }
+
+                session.translate(line);
+            }
+            // Ctrl-C abandons the current line, Ctrl-D exits
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(anyhow!("Failed to read input: {}", e)),
+        }
+    }
+
+    if let Some(path) = &history_file {
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
+
+impl Session {
+    fn translate(&mut self, line: &str) {
+        self.cancel.reset();
+        match translate_with_glossary(&self.gemma, self.direction, line, self.glossary.as_ref(), &self.cancel) {
+            Ok(translation) => {
+                println!("{}", translation.text);
+                if translation.fallback {
+                    eprintln!("(phrasebook fallback: model inference failed)");
+                }
+                for term in &translation.violations {
+                    eprintln!("(glossary: expected {} for {})", term.target, term.source);
+                }
+                self.history.push(Exchange {
+                    timestamp: SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or(Duration::from_secs(0))
+                        .as_secs(),
+                    direction: self.direction.as_str(),
+                    input: line.to_string(),
+                    output: translation.text,
+                    fallback: translation.fallback,
+                });
+            }
+            Err(TranslateError::Cancelled) => eprintln!("Cancelled"),
+            Err(e) => eprintln!("Translation error: {}", e),
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<Flow> {
+        // File names are the rest of the line, so they may contain spaces
+        let (name, rest) = split_word(command);
+        let (arg, tail) = split_word(rest);
+        match (name, arg, tail) {
+            ("dir", dir, "") if !dir.is_empty() => {
+                let direction = Direction::from_str(dir)
+                    .filter(|_| self.pairs.iter().any(|p| p == dir))
+                    .ok_or_else(|| anyhow!("Unsupported direction. Use one of: {}", self.pairs.join(", ")))?;
+                self.direction = direction;
+                println!("Direction: {}", direction.as_str());
+                // Terms were loaded for the old pair; load them again for the new one
+                if let Some(path) = self.glossary_path.clone() {
+                    self.glossary = None;
+                    match Glossary::load(&path, direction) {
+                        Ok(glossary) => {
+                            println!("Reloaded {} glossary terms", glossary.len());
+                            self.glossary = Some(glossary);
+                        }
+                        Err(e) => {
+                            self.glossary_path = None;
+                            return Err(anyhow!("Glossary dropped: {}", e));
+                        }
+                    }
+                }
+            }
+            ("temp", value, "") if !value.is_empty() => {
+                let temperature: f32 = value
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid temperature: {}", value))?;
+                if !(0.0..=2.0).contains(&temperature) {
+                    return Err(anyhow!("Temperature must be between 0 and 2"));
+                }
+                self.gemma.temperature = temperature;
+                println!("Temperature: {}", temperature);
+            }
+            ("glossary", "load", path) if !path.is_empty() => {
+                let glossary = Glossary::load(Path::new(path), self.direction)?;
+                println!("Loaded {} glossary terms", glossary.len());
+                self.glossary = Some(glossary);
+                self.glossary_path = Some(PathBuf::from(path));
+            }
+            ("glossary", "clear", "") => {
+                self.glossary = None;
+                self.glossary_path = None;
+                println!("Glossary cleared");
+            }
+            ("tm", "load", path) if !path.is_empty() => {
+                let units = memory::import_tmx(Path::new(path))?;
+                println!("Imported {} translation units", units);
+            }
+            ("tm", "clear", "") => {
+                memory::clear();
+                println!("Translation memory cleared");
+            }
+            ("save", _, _) if !rest.is_empty() => {
+                self.save(Path::new(rest))?;
+                println!("Saved {} translations to {}", self.history.len(), rest);
+            }
+            ("help", "", "") => println!("{}", HELP),
+            ("quit" | "q" | "exit", "", "") => return Ok(Flow::Quit),
+            _ => return Err(anyhow!("Unknown command :{}. Type :help for commands.", command)),
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let mut file = std::fs::File::create(path)
+            .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+        for exchange in &self.history {
+            writeln!(file, "{}", serde_json::to_string(exchange)?)?;
+        }
+        Ok(())
+    }
+}
+
+// First word of `text` and the rest of it, both trimmed
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".gemma_translator_history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        let cfg = Config::default();
+        Session {
+            gemma: cfg.gemma_config(),
+            direction: Direction::EsToEn,
+            pairs: cfg.languages.pairs.clone(),
+            glossary: None,
+            glossary_path: None,
+            history: Vec::new(),
+            cancel: CancelToken::new(),
+        }
+    }
+
+    fn error(session: &mut Session, command: &str) -> String {
+        match session.command(command) {
+            Ok(_) => panic!(":{} should fail", command),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn dir_switches_and_reloads_the_glossary() {
+        let tbx = r#"<tbx><text><body>
+          <termEntry>
+            <langSet xml:lang="es"><tig><term>infarto</term></tig></langSet>
+            <langSet xml:lang="en"><tig><term>heart attack</term></tig></langSet>
+          </termEntry>
+        </body></text></tbx>"#;
+        let path = std::env::temp_dir().join(format!("repl test {}.tbx", std::process::id()));
+        std::fs::write(&path, tbx).unwrap();
+
+        let mut session = session();
+        session.command(&format!("glossary load  {}", path.display())).unwrap();
+        assert_eq!(session.glossary_path.as_deref(), Some(path.as_path()));
+        assert_eq!(session.glossary.as_ref().unwrap().entries[0].source, "infarto");
+
+        session.command("dir en-es").unwrap();
+        assert_eq!(session.direction, Direction::EnToEs);
+        assert_eq!(session.glossary.as_ref().unwrap().entries[0].source, "heart attack");
+
+        // A glossary that can no longer be read is dropped, but the switch still happens
+        std::fs::remove_file(&path).unwrap();
+        assert!(error(&mut session, "dir es-en").starts_with("Glossary dropped"));
+        assert_eq!(session.direction, Direction::EsToEn);
+        assert!(session.glossary.is_none() && session.glossary_path.is_none());
+
+        assert!(error(&mut session, "dir fr-en").starts_with("Unsupported direction"));
+        session.pairs = vec!["es-en".to_string()];
+        assert!(error(&mut session, "dir en-es").starts_with("Unsupported direction"));
+        assert_eq!(session.direction, Direction::EsToEn);
+    }
+
+    #[test]
+    fn temp_stays_within_bounds() {
+        let mut session = session();
+        session.command("temp 0").unwrap();
+        assert_eq!(session.gemma.temperature, 0.0);
+        session.command("temp 2").unwrap();
+        assert_eq!(session.gemma.temperature, 2.0);
+        for value in ["2.1", "-0.5", "NaN"] {
+            assert_eq!(error(&mut session, &format!("temp {}", value)), "Temperature must be between 0 and 2");
+        }
+        assert_eq!(error(&mut session, "temp warm"), "Invalid temperature: warm");
+        assert_eq!(session.gemma.temperature, 2.0);
+    }
+
+    #[test]
+    fn rejects_missing_and_extra_arguments() {
+        let mut session = session();
+        let commands = [
+            "dir", "dir es-en en-es", "temp", "temp 0.5 1", "glossary", "glossary load", "glossary clear now", "tm load",
+            "tm clear all", "save", "help me", "quit now", "nope",
+        ];
+        for command in commands {
+            assert_eq!(error(&mut session, command), format!("Unknown command :{}. Type :help for commands.", command));
+        }
+        assert!(matches!(session.command(" quit "), Ok(Flow::Quit)));
+        assert!(matches!(session.command("glossary clear"), Ok(Flow::Continue)));
+    }
+
+    #[test]
+    fn save_takes_the_rest_of_the_line() {
+        let mut session = session();
+        session.history.push(Exchange {
+            timestamp: 1,
+            direction: "es-en",
+            input: "hola".to_string(),
+            output: "hello".to_string(),
+            fallback: false,
+        });
+        let path = std::env::temp_dir().join(format!("repl test {} history.jsonl", std::process::id()));
+        session.command(&format!("save {}", path.display())).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved, "{\"timestamp\":1,\"direction\":\"es-en\",\"input\":\"hola\",\"output\":\"hello\",\"fallback\":false}\n");
+    }
+}
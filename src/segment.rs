@@ -0,0 +1,195 @@
+//! Sentence segmentation and packing of long inputs into chunks the model can take in
+//! one run.
+//!
+//! Everything works on byte ranges into the original text, so the whitespace between
+//! chunks, including paragraph breaks, can be put back exactly as it was.
+
+use std::ops::Range;
+
+/// Chunks are never made smaller than this, however tight the configured limits
+pub const MIN_CHUNK_TOKENS: usize = 16;
+
+// Words that end in a period without ending the sentence, lowercased and without the
+// final period. "etc." is left out since it usually does end one.
+const ABBREVIATIONS_EN: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "e.g", "i.e", "approx", "dept", "fig", "inc",
+    "ltd", "co", "no", "vol", "ca", "cf", "jan", "feb", "mar", "apr", "jun", "jul", "aug", "sep", "sept",
+    "oct", "nov", "dec",
+];
+const ABBREVIATIONS_ES: &[&str] = &[
+    "sr", "sra", "srta", "sres", "dr", "dra", "prof", "lic", "ing", "ud", "uds", "vd", "vds", "d", "dña",
+    "pág", "págs", "núm", "no", "art", "cap", "ej", "p.ej", "av", "avda", "dpto", "tel", "aprox", "admón",
+    "ene", "feb", "mar", "abr", "jun", "jul", "ago", "sept", "oct", "nov", "dic",
+];
+
+/// Rough token count for budgeting. No tokenizer is available outside llama.cpp; three
+/// characters per token overestimates for English and Spanish, which errs on the side of
+/// chunks that fit.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(3)
+}
+
+/// Byte ranges of the sentences in `text`, for source language `lang` (e.g. `es`).
+/// Leading and trailing whitespace belongs to no sentence; the whitespace between two
+/// sentences is the gap between their ranges. A line break always ends a sentence.
+pub fn sentences(text: &str, lang: &str) -> Vec<Range<usize>> {
+    let abbreviations = match lang {
+        "es" => ABBREVIATIONS_ES,
+        _ => ABBREVIATIONS_EN,
+    };
+    let mut sentences = Vec::new();
+    let mut start = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if !c.is_whitespace() {
+            start.get_or_insert(i);
+            continue;
+        }
+        let Some(from) = start else { continue };
+        // Take the whole run of whitespace
+        let mut end = i + c.len_utf8();
+        let mut line_break = c == '\n';
+        while let Some(&(j, w)) = chars.peek().filter(|(_, w)| w.is_whitespace()) {
+            line_break |= w == '\n';
+            end = j + w.len_utf8();
+            chars.next();
+        }
+        let next = text[end..].chars().next();
+        if line_break || ends_sentence(&text[from..i], next, abbreviations) {
+            sentences.push(from..i);
+            start = None;
+        }
+    }
+    if let Some(from) = start {
+        sentences.push(from..text.trim_end().len());
+    }
+    sentences
+}
+
+// Whether `before` ends a sentence, given the first character of the next word
+fn ends_sentence(before: &str, next: Option<char>, abbreviations: &[&str]) -> bool {
+    // A lowercase continuation means the period was not the end, e.g. "approx. ten"
+    if next.is_some_and(char::is_lowercase) {
+        return false;
+    }
+    let core = before.trim_end_matches(['"', '\'', '”', '’', '»', ')', ']']);
+    if core.ends_with(['!', '?', '…']) || core.ends_with("...") {
+        return true;
+    }
+    let Some(word) = core.strip_suffix('.') else { return false };
+    let word = word.rsplit(|c: char| c.is_whitespace() || matches!(c, '(' | '"' | '«' | '¿' | '¡')).next().unwrap_or("");
+    let lower = word.to_lowercase();
+    // Initials such as the "J." in "J. Smith"
+    let initial = word.chars().count() == 1 && word.chars().all(char::is_uppercase);
+    !(initial || abbreviations.contains(&lower.as_str()))
+}
+
+/// Split any sentence over `budget` estimated tokens, preferring clause punctuation
+/// and otherwise cutting between words.
+pub fn fit(text: &str, sentences: Vec<Range<usize>>, budget: usize) -> Vec<Range<usize>> {
+    let mut fitted = Vec::with_capacity(sentences.len());
+    for sentence in sentences {
+        let mut start = sentence.start;
+        while estimate_tokens(&text[start..sentence.end]) > budget {
+            // Word boundaries in the rest of the sentence, with the text before each
+            let mut cut = None;
+            let mut clause_cut = None;
+            for (i, c) in text[start..sentence.end].char_indices() {
+                if !c.is_whitespace() || i == 0 {
+                    continue;
+                }
+                let end = start + i;
+                if estimate_tokens(&text[start..end]) > budget {
+                    break;
+                }
+                if text[start..end].ends_with([',', ';', ':']) {
+                    clause_cut = Some(end);
+                }
+                cut = Some(end);
+            }
+            // A single word over budget is left whole
+            let Some(end) = clause_cut.or(cut) else { break };
+            fitted.push(start..end);
+            start = end + text[end..].len() - text[end..].trim_start().len();
+        }
+        fitted.push(start..sentence.end);
+    }
+    fitted
+}
+
+/// Group consecutive sentences into chunks of at most `budget` estimated tokens.
+/// Returns ranges of sentence indices. Chunks run across line breaks, which stay in the
+/// chunk's text, so a multi-line input that fits the budget is a single run.
+pub fn pack(text: &str, sentences: &[Range<usize>], budget: usize) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut first = 0;
+    for i in 1..=sentences.len() {
+        let done = i == sentences.len()
+            || estimate_tokens(&text[sentences[first].start..sentences[i].end]) > budget;
+        if done {
+            chunks.push(first..i);
+            first = i;
+        }
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts<'a>(text: &'a str, ranges: &[Range<usize>]) -> Vec<&'a str> {
+        ranges.iter().map(|r| &text[r.clone()]).collect()
+    }
+
+    #[test]
+    fn splits_sentences_around_abbreviations() {
+        let text = "  Dr. Smith met J. Doe at 5 p.m. yesterday. It went well! Did it? \"Yes.\" Approx. ten left.  ";
+        assert_eq!(
+            texts(text, &sentences(text, "en")),
+            ["Dr. Smith met J. Doe at 5 p.m. yesterday.", "It went well!", "Did it?", "\"Yes.\"", "Approx. ten left."]
+        );
+    }
+
+    #[test]
+    fn uses_the_source_language_abbreviations() {
+        let text = "Habló con la Sra. García. Vino el Dr. Pérez.";
+        assert_eq!(texts(text, &sentences(text, "es")), ["Habló con la Sra. García.", "Vino el Dr. Pérez."]);
+        // "Sra" is not an English abbreviation
+        assert_eq!(sentences(text, "en").len(), 3);
+    }
+
+    #[test]
+    fn line_breaks_end_sentences() {
+        let text = "First line\nsecond line\n\n- item";
+        assert_eq!(texts(text, &sentences(text, "en")), ["First line", "second line", "- item"]);
+    }
+
+    #[test]
+    fn fit_prefers_clause_punctuation() {
+        let text = "one two three, four five six seven eight nine";
+        let fitted = fit(text, sentences(text, "en"), estimate_tokens("one two three, four"));
+        assert_eq!(texts(text, &fitted), ["one two three,", "four five six seven", "eight nine"]);
+    }
+
+    #[test]
+    fn fit_leaves_short_sentences_and_long_words_whole() {
+        let text = "Short. Supercalifragilisticexpialidocious";
+        let fitted = fit(text, sentences(text, "en"), 4);
+        assert_eq!(texts(text, &fitted), ["Short.", "Supercalifragilisticexpialidocious"]);
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)] // chunk index ranges, not one range to expand
+    fn pack_groups_sentences_within_the_budget() {
+        let text = "A rather long first sentence. Bbb.\nCcc. Ddd.";
+        let sentences = sentences(text, "en");
+        assert_eq!(pack(text, &sentences, estimate_tokens(text)), [0..4]);
+        // Across the line break
+        assert_eq!(pack(text, &sentences, estimate_tokens("Bbb.\nCcc. Ddd.")), [0..1, 1..4]);
+        assert_eq!(pack(text, &sentences, estimate_tokens("Bbb.\nCcc.")), [0..1, 1..3, 3..4]);
+        assert_eq!(pack(text, &sentences, 1), [0..1, 1..2, 2..3, 3..4]);
+        assert!(pack("", &[], 10).is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+//! Chat prompt templates per model family.
+//!
+//! The family is detected from the GGUF `tokenizer.chat_template` and
+//! `general.architecture` metadata. The Jinja template itself is not executed; each
+//! family has a built-in equivalent. A TOML template file can replace it for models
+//! the built-ins don't cover.
+
+use crate::gguf;
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Gemma,
+    Llama3,
+    /// ChatML, as used by Qwen
+    ChatMl,
+    Mistral,
+}
+
+impl Family {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "gemma" => Some(Family::Gemma),
+            "llama3" => Some(Family::Llama3),
+            "chatml" | "qwen" => Some(Family::ChatMl),
+            "mistral" => Some(Family::Mistral),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Family::Gemma => "gemma",
+            Family::Llama3 => "llama3",
+            Family::ChatMl => "chatml",
+            Family::Mistral => "mistral",
+        }
+    }
+
+    fn template(&self) -> Template {
+        let (prompt, marker, stop) = match self {
+            // Gemma has no system role; instructions go at the top of the user turn
+            Family::Gemma => (
+                "<start_of_turn>user\n{system}\n\n{user}<end_of_turn>\n<start_of_turn>model\n",
+                "<start_of_turn>model",
+                "<end_of_turn>",
+            ),
+            Family::Llama3 => (
+                "<|start_header_id|>system<|end_header_id|>\n\n{system}<|eot_id|>\
+                 <|start_header_id|>user<|end_header_id|>\n\n{user}<|eot_id|>\
+                 <|start_header_id|>assistant<|end_header_id|>\n\n",
+                "<|start_header_id|>assistant<|end_header_id|>",
+                "<|eot_id|>",
+            ),
+            Family::ChatMl => (
+                "<|im_start|>system\n{system}<|im_end|>\n<|im_start|>user\n{user}<|im_end|>\n<|im_start|>assistant\n",
+                "<|im_start|>assistant",
+                "<|im_end|>",
+            ),
+            // No system role here either
+            Family::Mistral => ("[INST] {system}\n\n{user} [/INST]", "[/INST]", "</s>"),
+        };
+        Template {
+            name: self.as_str().to_string(),
+            prompt: prompt.to_string(),
+            response_marker: marker.to_string(),
+            stop: vec![stop.to_string()],
+        }
+    }
+}
+
+/// How to wrap instructions and input for one model, and how to find its answer.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Template {
+    #[serde(default)]
+    pub name: String,
+    /// Full prompt with `{system}` and `{user}` placeholders
+    pub prompt: String,
+    /// Text at the end of the prompt after which the model's answer starts; llama.cpp
+    /// echoes the prompt before generating
+    pub response_marker: String,
+    /// End-of-turn sequences, added to the configured stop sequences
+    #[serde(default)]
+    pub stop: Vec<String>,
+}
+
+impl Template {
+    pub fn render(&self, system: &str, user: &str) -> String {
+        // Substitute in one pass so braces in the input are never taken as placeholders
+        let mut out = String::with_capacity(self.prompt.len() + system.len() + user.len());
+        let mut rest = self.prompt.as_str();
+        while let Some(open) = rest.find('{') {
+            out.push_str(&rest[..open]);
+            rest = &rest[open..];
+            if let Some(after) = rest.strip_prefix("{system}") {
+                out.push_str(system);
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("{user}") {
+                out.push_str(user);
+                rest = after;
+            } else {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Load a TOML template file with `prompt`, `response_marker` and optional `stop`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read prompt template {}: {}", path.display(), e))?;
+        let mut template: Template = toml::from_str(&text)
+            .map_err(|e| anyhow!("Invalid prompt template {}: {}", path.display(), e))?;
+        if !template.prompt.contains("{user}") {
+            return Err(anyhow!("Prompt template {} has no {{user}} placeholder", path.display()));
+        }
+        if !template.prompt.trim_end().ends_with(template.response_marker.trim_end()) || template.response_marker.trim().is_empty() {
+            return Err(anyhow!("Prompt template {}: the prompt must end with response_marker", path.display()));
+        }
+        if template.name.is_empty() {
+            template.name = path.display().to_string();
+        }
+        Ok(template)
+    }
+}
+
+// Model path, template setting and template file
+type TemplateKey = (String, String, Option<String>);
+
+lazy_static! {
+    // Resolved templates, so the GGUF header is read once per model
+    static ref RESOLVED: Mutex<HashMap<TemplateKey, Arc<Template>>> = Mutex::new(HashMap::new());
+}
+
+/// Template for `model_path`: the file when one is given, otherwise the named family,
+/// or for `auto` the family detected from the model's metadata.
+pub fn resolve(model_path: &str, family: &str, file: Option<&str>) -> Result<Arc<Template>> {
+    let key = (model_path.to_string(), family.to_string(), file.map(str::to_string));
+    if let Some(template) = RESOLVED.lock().unwrap().get(&key) {
+        return Ok(template.clone());
+    }
+
+    let template = match (file, family) {
+        (Some(file), _) => Template::load(Path::new(file))?,
+        (None, "auto") => detect(Path::new(model_path)).template(),
+        (None, name) => Family::from_str(name)
+            .ok_or_else(|| anyhow!("Unknown prompt template {}; use auto, gemma, llama3, chatml or mistral", name))?
+            .template(),
+    };
+    log::info!("Using the {} prompt template for {}", template.name, model_path);
+    let template = Arc::new(template);
+    RESOLVED.lock().unwrap().insert(key, template.clone());
+    Ok(template)
+}
+
+/// Family from the model's chat template markers, then from its architecture. Falls
+/// back to Gemma, the default model, when the file can't be read or is unknown.
+pub fn detect(model_path: &Path) -> Family {
+    let meta = match gguf::read_metadata(model_path) {
+        Ok(meta) => meta,
+        Err(e) => {
+            log::warn!("Cannot read model metadata ({}); assuming a Gemma prompt template", e);
+            return Family::Gemma;
+        }
+    };
+    log::debug!(
+        "{}: GGUF v{}, {} tensors, architecture {}",
+        model_path.display(),
+        meta.version,
+        meta.tensors.len(),
+        meta.architecture().unwrap_or("unknown")
+    );
+    family(&meta).unwrap_or_else(|| {
+        log::warn!("No prompt template known for architecture {:?}; assuming Gemma", meta.architecture());
+        Family::Gemma
+    })
+}
+
+/// Family with a built-in template matching the model, if any.
+pub fn family(meta: &gguf::Metadata) -> Option<Family> {
+    if let Some(template) = meta.chat_template() {
+        let markers = [
+            ("<start_of_turn>", Family::Gemma),
+            ("<|start_header_id|>", Family::Llama3),
+            ("<|im_start|>", Family::ChatMl),
+            ("[INST]", Family::Mistral),
+        ];
+        if let Some((_, family)) = markers.iter().find(|(marker, _)| template.contains(marker)) {
+            return Some(*family);
+        }
+    }
+    match meta.architecture()? {
+        arch if arch.starts_with("gemma") => Some(Family::Gemma),
+        arch if arch.starts_with("qwen") => Some(Family::ChatMl),
+        "llama" => Some(Family::Llama3),
+        "mistral" => Some(Family::Mistral),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn metadata(architecture: &str, chat_template: Option<&str>) -> gguf::Metadata {
+        let mut entries = BTreeMap::new();
+        entries.insert("general.architecture".to_string(), gguf::Value::String(architecture.to_string()));
+        if let Some(template) = chat_template {
+            entries.insert("tokenizer.chat_template".to_string(), gguf::Value::String(template.to_string()));
+        }
+        gguf::Metadata { version: 3, entries, tensors: Vec::new() }
+    }
+
+    #[test]
+    fn detects_the_family_from_the_chat_template_first() {
+        let cases = [
+            ("llama", Some("{{ '<|start_header_id|>' + role }}"), Some(Family::Llama3)),
+            // Fine-tunes keep the base architecture but bring their own chat format
+            ("llama", Some("{% for m in messages %}<|im_start|>{{ m.role }}"), Some(Family::ChatMl)),
+            ("gemma2", Some("<start_of_turn>user"), Some(Family::Gemma)),
+            ("llama", Some("[INST] {{ content }} [/INST]"), Some(Family::Mistral)),
+            ("gemma3", None, Some(Family::Gemma)),
+            ("qwen2", Some("{{ unknown markers }}"), Some(Family::ChatMl)),
+            ("mistral", None, Some(Family::Mistral)),
+            ("phi3", None, None),
+        ];
+        for (architecture, chat_template, expected) in cases {
+            assert_eq!(family(&metadata(architecture, chat_template)), expected, "{} {:?}", architecture, chat_template);
+        }
+    }
+
+    #[test]
+    fn unreadable_models_get_gemma() {
+        assert_eq!(detect(Path::new("/nonexistent/model.gguf")), Family::Gemma);
+    }
+
+    #[test]
+    fn renders_without_touching_braces_in_the_input() {
+        let template = Family::ChatMl.template();
+        let prompt = template.render("Translate {user}.", "Hi {system} {name}");
+        assert_eq!(
+            prompt,
+            "<|im_start|>system\nTranslate {user}.<|im_end|>\n<|im_start|>user\nHi {system} {name}<|im_end|>\n<|im_start|>assistant\n"
+        );
+        assert!(prompt.trim_end().ends_with(&template.response_marker));
+    }
+
+    #[test]
+    fn builtin_prompts_end_with_their_response_marker() {
+        for family in [Family::Gemma, Family::Llama3, Family::ChatMl, Family::Mistral] {
+            let template = family.template();
+            assert_eq!(Family::from_str(&template.name), Some(family));
+            assert!(template.render("S", "U").trim_end().ends_with(&template.response_marker), "{}", template.name);
+        }
+        assert_eq!(Family::from_str("qwen"), Some(Family::ChatMl));
+    }
+
+    #[test]
+    fn loads_and_checks_template_files() {
+        let path = std::env::temp_dir().join(format!("template-test-{}.toml", std::process::id()));
+        let load = |text: &str| {
+            std::fs::write(&path, text).unwrap();
+            Template::load(&path).map_err(|e| e.to_string())
+        };
+
+        let template = load("prompt = \"<s>{system} {user}\\nA:\"\nresponse_marker = \"A:\"\nstop = [\"\\n\"]\n").unwrap();
+        assert_eq!(template.name, path.display().to_string());
+        assert_eq!(template.render("sys", "text"), "<s>sys text\nA:");
+        assert_eq!(template.stop, ["\n"]);
+
+        let no_user = load("prompt = \"{system} A:\"\nresponse_marker = \"A:\"\n").unwrap_err();
+        assert!(no_user.ends_with("has no {user} placeholder"), "{}", no_user);
+        let no_marker = load("prompt = \"{user} A:\"\nresponse_marker = \"B:\"\n").unwrap_err();
+        assert!(no_marker.ends_with("the prompt must end with response_marker"), "{}", no_marker);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resolves_named_families() {
+        assert_eq!(resolve("/models/any.gguf", "llama3", None).unwrap().name, "llama3");
+        let err = resolve("/models/any.gguf", "alpaca", None).unwrap_err().to_string();
+        assert!(err.starts_with("Unknown prompt template alpaca"), "{}", err);
+    }
+}
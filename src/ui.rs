@@ -1,10 +1,22 @@
 #[cfg(feature = "ui")]
 pub mod ui {
-    use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+    use actix_multipart::form::{bytes::Bytes as UploadBytes, MultipartForm, MultipartFormConfig};
+    use actix_web::dev::Service;
+    use actix_web::{delete, get, post, web, App, Either, HttpRequest, HttpResponse, HttpServer, Responder};
     use serde::{Deserialize, Serialize};
+    use crate::asr::{AsrClient, AudioInput};
+    use crate::cancel::CancelToken;
+    use crate::config::Config;
+    use crate::error::Error;
+    use crate::gemma::{GemmaConfig, SamplingOverrides};
+    use crate::glossary::Glossary;
+    use crate::jobs::{JobRequest, JobStore};
+    use crate::models::{ActiveModel, LoadedModel};
+    use crate::vad::{UtteranceDetector, VadConfig, VadEvent};
     use sysinfo::{System, ProcessRefreshKind, RefreshKind, MemoryRefreshKind};
+    use std::collections::BTreeMap;
     use std::sync::{Arc, Mutex};
-    use std::time::{Duration, SystemTime};
+    use std::time::{Duration, Instant, SystemTime};
     use std::process;
     use lazy_static::lazy_static;
 
@@ -23,6 +35,7 @@ pub mod ui {
         peak_cpu: f32,
         peak_mem_mb: u64,
         performance_history: Vec<PerfData>,
+        cache: crate::cache::CacheStats,
     }
 
     #[derive(Default)]
@@ -121,6 +134,7 @@ pub mod ui {
             peak_cpu,
             peak_mem_mb: peak_mem,
             performance_history: history,
+            cache: crate::cache::stats(),
         })
     }
 
@@ -137,47 +151,816 @@ pub mod ui {
         }))
     }
 
+    #[post("/cache/clear")]
+    async fn clear_cache(http: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+        if let Err(e) = authorize_admin(&http, &state.config) {
+            return error_response(&e);
+        }
+        // Truncating the disk store is file I/O; keep it off the async worker
+        let cleared = web::block(crate::cache::clear)
+            .await
+            .unwrap_or_else(|e| Err(anyhow::anyhow!("Cache clear task failed: {}", e)));
+        match cleared {
+            Ok(cleared) => HttpResponse::Ok().json(serde_json::json!({
+                "ok": true,
+                "cleared": cleared
+            })),
+            Err(e) => {
+                log::error!("Failed to clear translation cache: {}", e);
+                error_response(&Error::Internal(e.to_string()))
+            }
+        }
+    }
+
+    /// Settings resolved once at startup and shared by every worker.
+    pub struct AppState {
+        pub config: Config,
+        /// Model new requests translate with; see `POST /models/active`
+        pub model: Arc<ActiveModel>,
+        pub asr: AsrClient,
+        pub jobs: JobStore,
+        pub started_at: u64,
+        /// Last ASR reachability probe, reused by `/readyz` for `ASR_PROBE_TTL`
+        asr_probe: Mutex<Option<(Instant, Result<String, String>)>>,
+    }
+
+    impl AppState {
+        /// Fails when the configured model file is missing, so the server never starts
+        /// on a setup where every translation would fall back to the phrasebook.
+        pub fn new(config: Config) -> std::io::Result<Self> {
+            let model = load_model(&config, config.gemma_config())?;
+            let started_at = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or(Duration::from_secs(0))
+                .as_secs();
+            let asr = AsrClient::new(config.asr_config()).map_err(std::io::Error::other)?;
+            let jobs = JobStore::new(Duration::from_secs(config.jobs.ttl_secs), config.jobs.max_queue);
+            Ok(Self {
+                config,
+                model: Arc::new(ActiveModel::new(model)),
+                asr,
+                jobs,
+                started_at,
+                asr_probe: Mutex::new(None),
+            })
+        }
+    }
+
+    fn load_model(config: &Config, gemma: GemmaConfig) -> std::io::Result<LoadedModel> {
+        let meta = std::fs::metadata(&gemma.model_path).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("Gemma model not found at {}: {}", gemma.model_path, e),
+            )
+        })?;
+        if !meta.is_file() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Gemma model path is not a file: {}", gemma.model_path),
+            ));
+        }
+        Ok(LoadedModel {
+            name: crate::models::name_of(&config.models, &gemma.model_path),
+            gemma,
+            size_mb: meta.len() / (1024 * 1024),
+        })
+    }
+
+    #[get("/info")]
+    async fn info(state: web::Data<AppState>) -> impl Responder {
+        let cfg = &state.config;
+        let model = state.model.get();
+        let model_name = std::path::Path::new(&model.gemma.model_path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // Resolved at startup, so this is a cache lookup
+        let template = model.gemma.prompt_template().map(|t| t.name.clone()).ok();
+        HttpResponse::Ok().json(serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "started_at": state.started_at,
+            "model": {
+                "name": model_name,
+                "registered_name": model.name,
+                "path": model.gemma.model_path,
+                "size_mb": model.size_mb,
+                "n_ctx": model.gemma.n_ctx,
+                "phrasebook_fallback": model.gemma.phrasebook_fallback,
+                "template": template,
+            },
+            "sampling": {
+                "max_tokens": model.gemma.max_tokens,
+                "temperature": model.gemma.temperature,
+                "top_k": model.gemma.top_k,
+                "top_p": model.gemma.top_p,
+                "min_p": model.gemma.min_p,
+                "repeat_penalty": model.gemma.repeat_penalty,
+                "seed": model.gemma.seed,
+                "stop": model.gemma.stop,
+            },
+            "asr": {
+                "backend": cfg.asr.backend,
+                "url": cfg.asr.url,
+            },
+            "languages": cfg.languages,
+            "glossaries": cfg.glossaries,
+            "translation_memory": {
+                "path": cfg.memory.path,
+                "units": crate::memory::len(),
+                "threshold": cfg.memory.threshold,
+            },
+            "jobs": {
+                "workers": cfg.jobs.workers,
+                "queued": state.jobs.queue_len(),
+                "max_queue": state.jobs.max_queue(),
+            },
+        }))
+    }
+
+    /// Probing the ASR backend costs a network round trip (and an OpenAI request), so
+    /// readiness polls share one result for this long.
+    const ASR_PROBE_TTL: Duration = Duration::from_secs(30);
+
+    #[derive(Serialize)]
+    struct Check {
+        ok: bool,
+        detail: String,
+    }
+
+    impl Check {
+        fn from_result(result: Result<String, String>) -> Self {
+            match result {
+                Ok(detail) => Check { ok: true, detail },
+                Err(detail) => Check { ok: false, detail },
+            }
+        }
+    }
+
+    /// Registered models with their metadata, the active one marked.
+    #[get("/models")]
+    async fn list_models(state: web::Data<AppState>) -> impl Responder {
+        let active = state.model.get();
+        let section = state.config.models.clone();
+        let active_path = std::fs::canonicalize(&active.gemma.model_path).ok();
+        // Reading headers touches every file, so keep it off the async workers
+        let entries = web::block(move || {
+            crate::models::registry(&section)
+                .into_iter()
+                .map(|model| {
+                    let read = crate::models::ModelInfo::read(std::path::Path::new(&model.settings.path));
+                    serde_json::json!({
+                        "name": model.name,
+                        "path": model.settings.path,
+                        "template": model.settings.template,
+                        "n_ctx": model.settings.n_ctx,
+                        "configured": model.configured,
+                        "active": active_path.is_some() && std::fs::canonicalize(&model.settings.path).ok() == active_path,
+                        "info": read.as_ref().ok(),
+                        "error": read.as_ref().err().map(|e| e.to_string()),
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+            .await;
+        match entries {
+            Ok(models) => HttpResponse::Ok().json(serde_json::json!({
+                "ok": true,
+                "active": active.name,
+                "models": models,
+            })),
+            Err(e) => error_response(&Error::Internal(format!("Listing models failed: {}", e))),
+        }
+    }
+
     #[derive(Deserialize)]
-    pub struct JobReq { direction: String, text: String }
+    pub struct SwitchModelReq { name: String }
+
+    /// Switch the model new requests use. Requests already running finish on the old
+    /// one, which is released when the last of them is done.
+    #[post("/models/active")]
+    async fn switch_model(
+        http: HttpRequest,
+        state: web::Data<AppState>,
+        req: web::Json<SwitchModelReq>,
+    ) -> impl Responder {
+        if let Err(e) = authorize_admin(&http, &state.config) {
+            return error_response(&e);
+        }
+        let mut config = state.config.clone();
+        let name = req.name.clone();
+        // Scanning the models directory and reading the header both touch the disk
+        let loaded = web::block(move || {
+            let registered = crate::models::find(&config.models, &name).map_err(|e| Error::NotFound(e.to_string()))?;
+            // The model's own template and context size, not the ones set for the
+            // startup model
+            registered.settings.apply(&mut config.gemma);
+            let gemma = config.gemma_config();
+            let unsupported = |e: String| Error::ModelUnsupported(e);
+            crate::models::validate(&gemma).map_err(|e| unsupported(e.to_string()))?;
+            gemma.prompt_template().map_err(|e| unsupported(e.to_string()))?;
+            let mut loaded = load_model(&config, gemma).map_err(|e| unsupported(e.to_string()))?;
+            loaded.name = Some(registered.name);
+            Ok::<_, Error>(loaded)
+        })
+            .await;
+        let loaded = match loaded {
+            Ok(Ok(loaded)) => loaded,
+            Ok(Err(e)) => return error_response(&e),
+            Err(e) => return error_response(&Error::Internal(format!("Loading model failed: {}", e))),
+        };
+
+        let path = loaded.gemma.model_path.clone();
+        let template = loaded.gemma.prompt_template().map(|t| t.name.clone()).ok();
+        let previous = state.model.switch(loaded);
+        // Our handle plus the one in each request still running
+        let draining = Arc::strong_count(&previous) - 1;
+        log::info!(
+            "Switched model from {} to {} ({} request(s) still on the old one)",
+            previous.gemma.model_path,
+            path,
+            draining
+        );
+        HttpResponse::Ok().json(serde_json::json!({
+            "ok": true,
+            "model": { "name": req.name, "path": path, "template": template },
+            "previous": { "name": previous.name, "path": previous.gemma.model_path },
+            "draining": draining,
+        }))
+    }
+
+    /// Liveness: the process is up and serving requests.
+    #[get("/healthz")]
+    async fn healthz(state: web::Data<AppState>) -> impl Responder {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs();
+        HttpResponse::Ok().json(serde_json::json!({
+            "ok": true,
+            "uptime_secs": now.saturating_sub(state.started_at),
+        }))
+    }
+
+    /// Readiness: translations will come from the model, ASR can be reached and the
+    /// job queue has room. Answers 503 with the failing checks otherwise.
+    #[get("/readyz")]
+    async fn readyz(state: web::Data<AppState>) -> impl Responder {
+        let active = state.model.get();
+        let model_path = &active.gemma.model_path;
+        let model = Check::from_result(match crate::gemma::is_gguf_file(model_path) {
+            Ok(true) => Ok(format!("{} ({} MB)", model_path, active.size_mb)),
+            Ok(false) => Err(format!("{} is not a GGUF model", model_path)),
+            Err(e) => Err(format!("Gemma model not readable at {}: {}", model_path, e)),
+        });
+
+        let llama = Check::from_result(
+            crate::gemma::find_llama_executable()
+                .map(|exe| exe.display().to_string())
+                .ok_or_else(|| "No llama.cpp executable found (tried llama, llama-cli, main, ./llama.cpp/main)".to_string()),
+        );
+
+        let asr = Check::from_result(probe_asr(&state).await);
+
+        let queued = state.jobs.queue_len();
+        let max_queue = state.jobs.max_queue();
+        let queue = Check {
+            ok: queued < max_queue,
+            detail: format!("{} of {} queue slots in use", queued, max_queue),
+        };
+
+        let ready = model.ok && llama.ok && asr.ok && queue.ok;
+        let body = serde_json::json!({
+            "ok": ready,
+            "checks": { "model": model, "llama": llama, "asr": asr, "queue": queue },
+        });
+        if ready {
+            HttpResponse::Ok().json(body)
+        } else {
+            HttpResponse::ServiceUnavailable().json(body)
+        }
+    }
+
+    async fn probe_asr(state: &AppState) -> Result<String, String> {
+        if let Some((at, result)) = state.asr_probe.lock().unwrap().as_ref() {
+            if at.elapsed() < ASR_PROBE_TTL {
+                return result.clone();
+            }
+        }
+        let result = state.asr.check_backend().await.map_err(|e| e.to_string());
+        *state.asr_probe.lock().unwrap() = Some((Instant::now(), result.clone()));
+        result
+    }
+
+    #[get("/metrics")]
+    async fn metrics(state: web::Data<AppState>) -> impl Responder {
+        HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4; charset=utf-8")
+            .body(crate::metrics::render(state.jobs.queue_len(), state.jobs.max_queue()))
+    }
+
+    /// Run a translation on actix's blocking pool so HTTP workers stay free while
+    /// llama.cpp runs. If the client disconnects, actix drops this future and the guard
+    /// kills the llama.cpp process instead of letting it run to completion.
+    async fn translate_blocking(
+        gemma: GemmaConfig,
+        direction: crate::gemma::Direction,
+        text: String,
+        glossary: Option<Glossary>,
+    ) -> Result<crate::gemma::Translation, Error> {
+        let cancel = CancelToken::new();
+        let _guard = cancel.drop_guard();
+        let translation = web::block(move || {
+            crate::gemma::translate_with_glossary(&gemma, direction, &text, glossary.as_ref(), &cancel)
+        })
+            .await
+            .map_err(|e| Error::Internal(format!("Translation task failed: {}", e)))??;
+        Ok(translation)
+    }
+
+    /// Error body carrying the stable `code`, sent with the status that code maps to.
+    fn error_response(err: &Error) -> HttpResponse {
+        error_response_with(err, serde_json::json!({}))
+    }
+
+    // Same as `error_response`, with extra fields (e.g. a transcript) merged into the body.
+    fn error_response_with(err: &Error, extra: serde_json::Value) -> HttpResponse {
+        let code = err.code();
+        let mut body = serde_json::json!({ "ok": false, "code": code, "error": err.to_string() });
+        if let (Some(body), serde_json::Value::Object(extra)) = (body.as_object_mut(), extra) {
+            body.extend(extra);
+        }
+        let status = actix_web::http::StatusCode::from_u16(code.http_status())
+            .unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponse::build(status).json(body)
+    }
+
+    /// Endpoints that change server state answer clients on loopback, or any client
+    /// with the configured admin token.
+    fn authorize_admin(req: &HttpRequest, cfg: &Config) -> Result<(), Error> {
+        let Some(token) = &cfg.server.admin_token else {
+            if req.peer_addr().is_some_and(|addr| addr.ip().to_canonical().is_loopback()) {
+                return Ok(());
+            }
+            return Err(Error::Forbidden(
+                "Only loopback clients may do this unless server.admin_token is set".to_string(),
+            ));
+        };
+        let given = req
+            .headers()
+            .get(actix_web::http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        // Compare every byte so the time taken doesn't give the token away
+        let matches = given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0;
+        if matches {
+            Ok(())
+        } else {
+            Err(Error::Forbidden("Missing or wrong admin token".to_string()))
+        }
+    }
+
+    fn invalid_direction(state: &AppState) -> Error {
+        Error::InvalidRequest(format!(
+            "Invalid direction. Use one of: {}",
+            state.config.languages.pairs.join(", ")
+        ))
+    }
+
+    #[derive(Deserialize)]
+    pub struct JobReq {
+        direction: String,
+        text: String,
+        /// Extra `source -> target` terms for this request, over the pair's configured glossary
+        #[serde(default)]
+        glossary: BTreeMap<String, String>,
+        /// Sampling settings for this request, over the configured ones
+        #[serde(default)]
+        sampling: SamplingOverrides,
+    }
 
     #[post("/translate")]
-    async fn translate(req: web::Json<JobReq>) -> impl Responder {
-        use crate::gemma::{translate as gemma_translate, Direction, GemmaConfig};
+    async fn translate(state: web::Data<AppState>, req: web::Json<JobReq>) -> impl Responder {
+        use crate::gemma::Direction;
+        let cfg = &state.config;
         
         // Parse direction
         let direction = match Direction::from_str(&req.direction) {
-            Some(dir) => dir,
-            None => {
-                return HttpResponse::BadRequest().json(serde_json::json!({
-                    "ok": false,
-                    "error": "Invalid direction. Use 'es-en' or 'en-es'"
-                }));
+            Some(dir) if cfg.languages.is_enabled(&req.direction) => dir,
+            _ => return error_response(&invalid_direction(&state)),
+        };
+        let glossary = if req.glossary.is_empty() {
+            None
+        } else {
+            match Glossary::from_terms(&req.glossary) {
+                Ok(glossary) => Some(glossary),
+                Err(e) => return error_response(&Error::InvalidRequest(e.to_string())),
             }
         };
-        
-        // Use hardcoded model path for UI (you can make this configurable later)
-        let gemma_cfg = GemmaConfig {
-            model_path: "models/gemma-2b-it.Q4_K_M.gguf".to_string(),
-            n_ctx: 2048,
+        // Held until the translation is done, so a model switch waits for it
+        let model = state.model.get();
+        let gemma = match req.sampling.apply(&model.gemma) {
+            Ok(gemma) => gemma,
+            Err(e) => return error_response(&Error::InvalidRequest(format!("Invalid sampling: {}", e))),
         };
         
         // Perform translation
-        match gemma_translate(&gemma_cfg, direction, &req.text) {
-            Ok(translated_text) => {
+        match translate_blocking(gemma, direction, req.text.clone(), glossary).await {
+            Ok(translation) => {
                 HttpResponse::Ok().json(serde_json::json!({
                     "ok": true,
                     "direction": req.direction,
                     "original": req.text,
-                    "translated": translated_text
+                    "translated": translation.text,
+                    "fallback": translation.fallback,
+                    "glossary_violations": translation.violations
                 }))
             }
             Err(e) => {
                 log::error!("Translation failed: {}", e);
-                HttpResponse::InternalServerError().json(serde_json::json!({
-                    "ok": false,
-                    "error": format!("Translation failed: {}", e)
-                }))
+                error_response(&e)
+            }
+        }
+    }
+
+    // Long WAV recordings are split for Whisper; this only bounds what we buffer
+    const MAX_AUDIO_BYTES: usize = 200 * 1024 * 1024;
+
+    #[derive(MultipartForm)]
+    pub struct AudioUpload {
+        file: UploadBytes,
+    }
+
+    #[derive(Deserialize)]
+    pub struct AudioQuery { direction: Option<String> }
+
+    type AudioBody = Either<MultipartForm<AudioUpload>, web::Bytes>;
+
+    // Accept either a multipart `file` field (any format Whisper understands) or a raw WAV body.
+    fn audio_from_body(body: AudioBody) -> Result<AudioInput, Error> {
+        match body {
+            Either::Left(form) => {
+                let file = form.into_inner().file;
+                if file.data.is_empty() {
+                    return Err(Error::InvalidRequest("Uploaded audio is empty".to_string()));
+                }
+                Ok(AudioInput {
+                    bytes: file.data.to_vec(),
+                    file_name: file.file_name.unwrap_or_else(|| "audio.wav".to_string()),
+                    mime: file.content_type.map(|m| m.to_string()).unwrap_or_else(|| "audio/wav".to_string()),
+                })
             }
+            Either::Right(bytes) => Ok(AudioInput::wav(bytes.to_vec())?),
+        }
+    }
+
+    #[post("/transcribe")]
+    async fn transcribe(state: web::Data<AppState>, body: AudioBody) -> impl Responder {
+        let audio = match audio_from_body(body) {
+            Ok(audio) => audio,
+            Err(e) => return error_response(&e),
+        };
+
+        match state.asr.transcribe_detailed(&audio).await {
+            Ok(transcript) => HttpResponse::Ok().json(serde_json::json!({
+                "ok": true,
+                "transcript": transcript.text,
+                "segments": transcript.segments
+            })),
+            Err(e) => {
+                log::error!("Transcription failed: {}", e);
+                error_response(&e.into())
+            }
+        }
+    }
+
+    #[post("/speech-translate")]
+    async fn speech_translate(
+        state: web::Data<AppState>,
+        query: web::Query<AudioQuery>,
+        body: AudioBody,
+    ) -> impl Responder {
+        use crate::gemma::Direction;
+        let cfg = &state.config;
+
+        let direction_str = query.direction.clone().unwrap_or_else(|| cfg.languages.default_direction.clone());
+        let direction = match Direction::from_str(&direction_str) {
+            Some(dir) if cfg.languages.is_enabled(&direction_str) => dir,
+            _ => return error_response(&invalid_direction(&state)),
+        };
+
+        let audio = match audio_from_body(body) {
+            Ok(audio) => audio,
+            Err(e) => return error_response(&e),
+        };
+
+        let transcript = match state.asr.transcribe(&audio).await {
+            Ok(text) => text,
+            Err(e) => {
+                log::error!("Transcription failed: {}", e);
+                return error_response(&e.into());
+            }
+        };
+
+        let model = state.model.get();
+        match translate_blocking(model.gemma.clone(), direction, transcript.clone(), None).await {
+            Ok(translation) => HttpResponse::Ok().json(serde_json::json!({
+                "ok": true,
+                "direction": direction_str,
+                "transcript": transcript,
+                "translated": translation.text,
+                "fallback": translation.fallback,
+                "glossary_violations": translation.violations
+            })),
+            Err(e) => {
+                log::error!("Translation failed: {}", e);
+                error_response_with(&e, serde_json::json!({ "transcript": transcript }))
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct TextJobReq { direction: Option<String>, text: String }
+
+    /// Queue a long translation. JSON bodies (`{"direction", "text"}`) create text jobs;
+    /// multipart or raw WAV bodies create audio jobs, with `?direction=` in the query.
+    #[post("/jobs")]
+    async fn create_job(
+        state: web::Data<AppState>,
+        query: web::Query<AudioQuery>,
+        body: Either<web::Json<TextJobReq>, AudioBody>,
+    ) -> impl Responder {
+        use crate::gemma::Direction;
+        let cfg = &state.config;
+
+        let requested = match &body {
+            Either::Left(req) => req.direction.clone(),
+            Either::Right(_) => query.direction.clone(),
+        };
+        let direction_str = requested.unwrap_or_else(|| cfg.languages.default_direction.clone());
+        let direction = match Direction::from_str(&direction_str) {
+            Some(dir) if cfg.languages.is_enabled(&direction_str) => dir,
+            _ => return error_response(&invalid_direction(&state)),
+        };
+
+        let request = match body {
+            Either::Left(req) => JobRequest::Text { direction, text: req.into_inner().text },
+            Either::Right(audio) => match audio_from_body(audio) {
+                Ok(audio) => JobRequest::Audio { direction, audio },
+                Err(e) => return error_response(&e),
+            },
+        };
+
+        match state.jobs.submit(request) {
+            Ok(id) => HttpResponse::Accepted().json(serde_json::json!({
+                "ok": true,
+                "id": id,
+                "status": "queued"
+            })),
+            Err(e) => error_response(&e),
+        }
+    }
+
+    #[get("/jobs/{id}")]
+    async fn get_job(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+        match state.jobs.get(&id) {
+            Some(job) => HttpResponse::Ok().json(serde_json::json!({ "ok": true, "job": job })),
+            None => error_response(&Error::NotFound(format!("Unknown or expired job: {}", id))),
+        }
+    }
+
+    #[delete("/jobs/{id}")]
+    async fn cancel_job(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+        match state.jobs.cancel(&id) {
+            Some(status) => HttpResponse::Ok().json(serde_json::json!({
+                "ok": true,
+                "id": id.into_inner(),
+                "status": status
+            })),
+            None => error_response(&Error::NotFound(format!("Unknown or expired job: {}", id))),
+        }
+    }
+
+    // Browsers resample microphone audio to this rate before streaming it
+    const LIVE_SAMPLE_RATE: u32 = 16000;
+
+    /// Server-to-client messages on `/ws/live`.
+    #[derive(Serialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum LiveEvent {
+        Ready { sample_rate: u32, direction: String },
+        Partial { text: String },
+        Final { text: String },
+        /// Raw model output as it is generated; provisional until `Translation` brings
+        /// the cleaned text of the whole utterance
+        Token { text: String },
+        Translation { text: String, fallback: bool },
+        Error { code: crate::error::ErrorCode, message: String },
+    }
+
+    impl LiveEvent {
+        fn error(err: impl Into<Error>) -> Self {
+            let err = err.into();
+            LiveEvent::Error { code: err.code(), message: err.to_string() }
+        }
+    }
+
+    /// Client-to-server control messages; audio itself arrives as binary frames.
+    #[derive(Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum LiveControl {
+        Direction { direction: String },
+        Flush,
+    }
+
+    #[derive(Deserialize)]
+    pub struct LiveQuery { direction: Option<String> }
+
+    /// Live captioning: binary frames carry mono 16 kHz little-endian i16 PCM. The
+    /// server segments speech with VAD, transcribes it, and streams the translation back.
+    #[get("/ws/live")]
+    async fn ws_live(
+        state: web::Data<AppState>,
+        query: web::Query<LiveQuery>,
+        req: HttpRequest,
+        body: web::Payload,
+    ) -> actix_web::Result<HttpResponse> {
+        let (response, session, stream) = actix_ws::handle(&req, body)?;
+        let direction = query.direction.clone().unwrap_or_else(|| state.config.languages.default_direction.clone());
+        actix_web::rt::spawn(live_session(state.into_inner(), direction, session, stream));
+        Ok(response)
+    }
+
+    async fn send_event(session: &mut actix_ws::Session, event: &LiveEvent) -> Result<(), actix_ws::Closed> {
+        let json = serde_json::to_string(event).unwrap_or_default();
+        session.text(json).await
+    }
+
+    async fn live_session(
+        state: Arc<AppState>,
+        mut direction: String,
+        mut session: actix_ws::Session,
+        mut stream: actix_ws::MessageStream,
+    ) {
+        use actix_ws::Message;
+
+        if !state.config.languages.is_enabled(&direction) {
+            let _ = send_event(&mut session, &LiveEvent::error(Error::InvalidRequest(format!("Invalid direction: {}", direction)))).await;
+            let _ = session.close(None).await;
+            return;
+        }
+
+        let mut vad = UtteranceDetector::new(VadConfig { sample_rate: LIVE_SAMPLE_RATE, ..VadConfig::default() });
+        let ready = LiveEvent::Ready { sample_rate: LIVE_SAMPLE_RATE, direction: direction.clone() };
+        if send_event(&mut session, &ready).await.is_err() {
+            return;
+        }
+        // Every partial re-sends the whole utterance so far; only worth it when the
+        // transcription costs nothing
+        let partials = state.asr.is_local();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let worker = actix_web::rt::spawn(live_worker(state.clone(), session.clone(), rx));
+        let mut leftover: Option<u8> = None;
+
+        while let Some(Ok(msg)) = stream.recv().await {
+            let events = match msg {
+                Message::Binary(bytes) => {
+                    // A sample may straddle two frames; its first byte waits for the next one
+                    let mut data = Vec::with_capacity(bytes.len() + 1);
+                    data.extend(leftover.take());
+                    data.extend_from_slice(&bytes);
+                    let pairs = data.chunks_exact(2);
+                    leftover = pairs.remainder().first().copied();
+                    let samples: Vec<i16> = pairs.map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+                    vad.push(&samples)
+                }
+                Message::Text(text) => match serde_json::from_str::<LiveControl>(&text) {
+                    Ok(LiveControl::Direction { direction: requested }) => {
+                        if state.config.languages.is_enabled(&requested) {
+                            direction = requested;
+                        } else if send_event(&mut session, &LiveEvent::error(Error::InvalidRequest(format!("Invalid direction: {}", requested)))).await.is_err() {
+                            return;
+                        }
+                        Vec::new()
+                    }
+                    Ok(LiveControl::Flush) => vad.flush().into_iter().collect(),
+                    Err(e) => {
+                        if send_event(&mut session, &LiveEvent::error(Error::InvalidRequest(format!("Invalid control message: {}", e)))).await.is_err() {
+                            return;
+                        }
+                        Vec::new()
+                    }
+                },
+                Message::Ping(bytes) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                    Vec::new()
+                }
+                Message::Close(_) => break,
+                _ => Vec::new(),
+            };
+
+            for event in events {
+                if partials || matches!(event, VadEvent::Final(_)) {
+                    let _ = tx.send((direction.clone(), event));
+                }
+            }
+        }
+
+        // Stops a transcription or translation still running for the closed socket
+        worker.abort();
+        let _ = session.close(None).await;
+    }
+
+    // Transcribes and translates utterances one at a time, apart from the receive loop
+    // so pings, control messages and close frames are answered meanwhile
+    async fn live_worker(
+        state: Arc<AppState>,
+        mut session: actix_ws::Session,
+        mut rx: tokio::sync::mpsc::UnboundedReceiver<(String, VadEvent)>,
+    ) {
+        let mut queue = std::collections::VecDeque::new();
+        loop {
+            if queue.is_empty() {
+                match rx.recv().await {
+                    Some(job) => queue.push_back(job),
+                    None => return,
+                }
+            }
+            while let Ok(job) = rx.try_recv() {
+                queue.push_back(job);
+            }
+            let Some((direction, event)) = queue.pop_front() else { continue };
+            // A partial with anything queued behind it is already out of date
+            if matches!(event, VadEvent::Partial(_)) && !queue.is_empty() {
+                continue;
+            }
+            if handle_vad_event(&state, &direction, &mut session, event).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn handle_vad_event(
+        state: &AppState,
+        direction: &str,
+        session: &mut actix_ws::Session,
+        event: VadEvent,
+    ) -> Result<(), actix_ws::Closed> {
+        use crate::gemma::{translate_streaming, Direction};
+
+        let (samples, is_final) = match event {
+            VadEvent::Partial(samples) => (samples, false),
+            VadEvent::Final(samples) => (samples, true),
+        };
+
+        let transcript = match AudioInput::from_pcm(&samples, LIVE_SAMPLE_RATE) {
+            Ok(audio) => state.asr.transcribe(&audio).await,
+            Err(e) => Err(e),
+        };
+        let text = match transcript {
+            Ok(text) => text,
+            Err(e) => {
+                // Partials are best-effort; silence misdetected as speech is not worth reporting
+                log::debug!("Live transcription failed: {}", e);
+                if is_final {
+                    return send_event(session, &LiveEvent::error(e)).await;
+                }
+                return Ok(());
+            }
+        };
+
+        if !is_final {
+            return send_event(session, &LiveEvent::Partial { text }).await;
+        }
+        send_event(session, &LiveEvent::Final { text: text.clone() }).await?;
+
+        // Generation blocks, so run it on the blocking pool and relay tokens as they arrive
+        let Some(dir) = Direction::from_str(direction) else { return Ok(()) };
+        let model = state.model.get();
+        // Returning early because the socket closed, or the worker being aborted, drops
+        // the guard and stops llama.cpp
+        let cancel = CancelToken::new();
+        let _guard = cancel.drop_guard();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let handle = actix_web::rt::task::spawn_blocking(move || {
+            translate_streaming(&model.gemma, dir, &text, None, &cancel, &mut |token| {
+                let _ = tx.send(token.to_string());
+            })
+        });
+
+        while let Some(token) = rx.recv().await {
+            send_event(session, &LiveEvent::Token { text: token }).await?;
+        }
+
+        match handle.await {
+            Ok(Ok(translation)) => {
+                let event = LiveEvent::Translation { text: translation.text, fallback: translation.fallback };
+                send_event(session, &event).await
+            }
+            Ok(Err(e)) => send_event(session, &LiveEvent::error(e)).await,
+            Err(e) => send_event(session, &LiveEvent::error(Error::Internal(format!("Translation task failed: {}", e)))).await,
         }
     }
 
@@ -193,18 +976,59 @@ pub mod ui {
         HttpResponse::Ok().content_type("text/css; charset=utf-8").body(css)
     }
 
-    pub async fn run(port: u16) -> std::io::Result<()> {
+    pub async fn run(cfg: Config) -> std::io::Result<()> {
+        let bind = (cfg.server.bind.clone(), cfg.server.port);
+        let workers = cfg.server.workers;
+        let state = web::Data::new(AppState::new(cfg)?);
+        log::info!("Serving model {}", state.model.get().gemma.model_path);
+        state.jobs.spawn_workers(state.config.jobs.workers, state.model.clone(), state.asr.clone());
+
         // Initialize the system for better CPU tracking
-        {
-            let mut sys = SYSTEM.lock().unwrap();
-            sys.refresh_all();
-            // Wait a bit to allow initial CPU measurement
-            std::thread::sleep(Duration::from_millis(500));
-            sys.refresh_all();
-        }
+        SYSTEM.lock().unwrap().refresh_all();
+        // Wait a bit to allow initial CPU measurement, without parking the runtime thread
+        actix_web::rt::time::sleep(Duration::from_millis(500)).await;
+        SYSTEM.lock().unwrap().refresh_all();
         
-        HttpServer::new(|| App::new().service(index).service(styles).service(stats).service(reset_stats).service(translate))
-            .bind(("0.0.0.0", port))?
+        HttpServer::new(move || {
+            App::new()
+                // Count every response by route pattern so ids in paths don't explode the label set
+                .wrap_fn(|req, srv| {
+                    let method = req.method().to_string();
+                    let endpoint = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+                    let response = srv.call(req);
+                    async move {
+                        let response = response.await?;
+                        crate::metrics::record_request(&method, &endpoint, response.status().as_u16());
+                        Ok(response)
+                    }
+                })
+                .app_data(state.clone())
+                .app_data(web::PayloadConfig::new(MAX_AUDIO_BYTES))
+                .app_data(MultipartFormConfig::default().total_limit(MAX_AUDIO_BYTES).memory_limit(MAX_AUDIO_BYTES))
+                .service(index)
+                .service(styles)
+                .service(stats)
+                .service(reset_stats)
+                .service(clear_cache)
+                .service(translate)
+                .service(info)
+                .service(list_models)
+                .service(switch_model)
+                .service(metrics)
+                .service(healthz)
+                .service(readyz)
+                .service(transcribe)
+                .service(speech_translate)
+                .service(ws_live)
+                .service(create_job)
+                .service(get_job)
+                .service(cancel_job)
+        })
+            .workers(workers)
+            // Treat a closed read half as a disconnect so abandoned requests are dropped
+            // and their inference cancelled
+            .h1_allow_half_closed(false)
+            .bind(bind)?
             .run()
             .await
     }
@@ -0,0 +1,155 @@
+//! Energy-based voice activity detection that cuts a PCM stream into utterances.
+//!
+//! Audio is mono 16-bit PCM. Frames whose RMS level exceeds `threshold` count as
+//! speech; an utterance ends after `hangover_ms` of consecutive silence.
+
+/// Tuning for an [`UtteranceDetector`].
+#[derive(Debug, Clone)]
+pub struct VadConfig {
+    pub sample_rate: u32,
+    pub frame_ms: u32,
+    /// RMS level (0.0 - 1.0) above which a frame counts as speech
+    pub threshold: f32,
+    pub hangover_ms: u32,
+    /// How much new speech to accumulate before emitting another partial
+    pub partial_every_ms: u32,
+    /// Utterances are force-finalized at this length so ASR calls stay bounded
+    pub max_utterance_ms: u32,
+    /// Audio kept from before speech onset so the first syllable is not clipped
+    pub pre_roll_ms: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 16000,
+            frame_ms: 30,
+            threshold: 0.015,
+            hangover_ms: 700,
+            partial_every_ms: 1500,
+            max_utterance_ms: 15000,
+            pre_roll_ms: 300,
+        }
+    }
+}
+
+pub enum VadEvent {
+    /// Speech so far in the current utterance
+    Partial(Vec<i16>),
+    /// A complete utterance
+    Final(Vec<i16>),
+}
+
+pub struct UtteranceDetector {
+    cfg: VadConfig,
+    frame_len: usize,
+    pending: Vec<i16>,
+    pre_roll: Vec<i16>,
+    utterance: Vec<i16>,
+    in_speech: bool,
+    silence_ms: u32,
+    since_partial_ms: u32,
+}
+
+impl UtteranceDetector {
+    pub fn new(cfg: VadConfig) -> Self {
+        let frame_len = (cfg.sample_rate * cfg.frame_ms / 1000).max(1) as usize;
+        Self {
+            cfg,
+            frame_len,
+            pending: Vec::new(),
+            pre_roll: Vec::new(),
+            utterance: Vec::new(),
+            in_speech: false,
+            silence_ms: 0,
+            since_partial_ms: 0,
+        }
+    }
+
+    /// Feed samples and collect any partial or final utterances they complete.
+    pub fn push(&mut self, samples: &[i16]) -> Vec<VadEvent> {
+        self.pending.extend_from_slice(samples);
+        let mut events = Vec::new();
+
+        let mut offset = 0;
+        while self.pending.len() - offset >= self.frame_len {
+            let frame = self.pending[offset..offset + self.frame_len].to_vec();
+            offset += self.frame_len;
+            if let Some(event) = self.process_frame(&frame) {
+                events.push(event);
+            }
+        }
+        self.pending.drain(..offset);
+
+        events
+    }
+
+    /// Finalize whatever speech is buffered, e.g. when the client stops streaming.
+    pub fn flush(&mut self) -> Option<VadEvent> {
+        let had_speech = self.in_speech;
+        self.utterance.append(&mut self.pending);
+        let utterance = self.reset();
+        if had_speech && !utterance.is_empty() {
+            Some(VadEvent::Final(utterance))
+        } else {
+            None
+        }
+    }
+
+    fn process_frame(&mut self, frame: &[i16]) -> Option<VadEvent> {
+        let is_speech = rms(frame) >= self.cfg.threshold;
+
+        if !self.in_speech {
+            if !is_speech {
+                self.pre_roll.extend_from_slice(frame);
+                let keep = (self.cfg.sample_rate * self.cfg.pre_roll_ms / 1000) as usize;
+                if self.pre_roll.len() > keep {
+                    let excess = self.pre_roll.len() - keep;
+                    self.pre_roll.drain(..excess);
+                }
+                return None;
+            }
+            self.in_speech = true;
+            self.utterance = std::mem::take(&mut self.pre_roll);
+        }
+
+        self.utterance.extend_from_slice(frame);
+        self.since_partial_ms += self.cfg.frame_ms;
+        if is_speech {
+            self.silence_ms = 0;
+        } else {
+            self.silence_ms += self.cfg.frame_ms;
+        }
+
+        let length_ms = (self.utterance.len() as u64 * 1000 / self.cfg.sample_rate as u64) as u32;
+        if self.silence_ms >= self.cfg.hangover_ms || length_ms >= self.cfg.max_utterance_ms {
+            return Some(VadEvent::Final(self.reset()));
+        }
+        if self.since_partial_ms >= self.cfg.partial_every_ms {
+            self.since_partial_ms = 0;
+            return Some(VadEvent::Partial(self.utterance.clone()));
+        }
+        None
+    }
+
+    fn reset(&mut self) -> Vec<i16> {
+        self.in_speech = false;
+        self.silence_ms = 0;
+        self.since_partial_ms = 0;
+        std::mem::take(&mut self.utterance)
+    }
+}
+
+fn rms(frame: &[i16]) -> f32 {
+    if frame.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = frame
+        .iter()
+        .map(|&s| {
+            let v = s as f64 / i16::MAX as f64;
+            v * v
+        })
+        .sum();
+    (sum / frame.len() as f64).sqrt() as f32
+}
@@ -0,0 +1,124 @@
+//! Just enough XML scanning for the TMX and TBX exchange formats. There is no DTD,
+//! namespace or nesting support: an element ends at the first matching close tag.
+
+use anyhow::{anyhow, Result};
+
+pub struct Element<'a> {
+    /// The start tag without the angle brackets, e.g. `tuv xml:lang="es"`
+    pub tag: &'a str,
+    pub content: &'a str,
+}
+
+impl<'a> Element<'a> {
+    pub fn attribute(&self, name: &str) -> Option<&'a str> {
+        for quote in ['"', '\''] {
+            let needle = format!("{}={}", name, quote);
+            // Require a separator so `lang` doesn't match inside `xml:lang` or `srclang`
+            let found = self.tag.match_indices(&needle).find(|(pos, _)| {
+                self.tag[..*pos].ends_with(char::is_whitespace)
+            });
+            if let Some((pos, _)) = found {
+                let value = &self.tag[pos + needle.len()..];
+                return value.find(quote).map(|end| &value[..end]);
+            }
+        }
+        None
+    }
+
+    /// `xml:lang` reduced to its primary subtag, so `es-ES` becomes `es`.
+    pub fn lang(&self) -> Option<String> {
+        let lang = self.attribute("xml:lang").or_else(|| self.attribute("lang"))?;
+        let primary = lang.split(['-', '_']).next().unwrap_or_default().to_lowercase();
+        (!primary.is_empty()).then_some(primary)
+    }
+}
+
+/// Every `<name>` element in `text`, in document order.
+pub fn elements<'a>(text: &'a str, name: &str) -> Result<Vec<Element<'a>>> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut found = Vec::new();
+    let mut rest = text;
+
+    while let Some(pos) = rest.find(&open) {
+        let after = &rest[pos + open.len()..];
+        // `<tu` must not match `<tuv`
+        if !after.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            rest = after;
+            continue;
+        }
+        let tag_len = after.find('>').ok_or_else(|| anyhow!("unterminated <{}> tag", name))?;
+        let tag = &rest[pos + 1..pos + open.len() + tag_len];
+        let body = &after[tag_len + 1..];
+        if tag.ends_with('/') {
+            found.push(Element { tag, content: "" });
+            rest = body;
+            continue;
+        }
+        let end = body.find(&close).ok_or_else(|| anyhow!("missing {}", close))?;
+        found.push(Element { tag, content: &body[..end] });
+        rest = &body[end + close.len()..];
+    }
+    Ok(found)
+}
+
+/// Content of the first `<name>` element, if any.
+pub fn first<'a>(text: &'a str, name: &str) -> Result<Option<&'a str>> {
+    Ok(elements(text, name)?.into_iter().next().map(|e| e.content))
+}
+
+/// Remove any remaining tags, keeping their text content.
+pub fn strip_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(lt) = rest.find('<') {
+        out.push_str(&rest[..lt]);
+        match rest[lt..].find('>') {
+            Some(gt) => rest = &rest[lt + gt + 1..],
+            None => {
+                rest = "";
+                break;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decode the predefined entities and numeric character references.
+pub fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|&i| i <= 10).and_then(|semi| {
+            let entity = &rest[1..semi];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, semi))
+        });
+        match decoded {
+            Some((c, semi)) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,43 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+pub fn write_executable(path: &Path, body: &str) {
+    std::fs::write(path, body).unwrap();
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Write the header of a GGUF v3 Gemma instruct model: enough metadata and one tensor
+/// to pass the startup checks, with no weights behind it.
+pub fn write_model(path: &Path) {
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+    let entries = [
+        ("general.architecture", "gemma"),
+        ("general.name", "Gemma 2b It"),
+        ("tokenizer.chat_template", "{{ '<start_of_turn>' + role + '\n' + message['content'] + '<end_of_turn>\n' }}"),
+    ];
+
+    let mut out = b"GGUF".to_vec();
+    out.extend_from_slice(&3u32.to_le_bytes());
+    out.extend_from_slice(&1u64.to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    for (key, value) in entries {
+        string(&mut out, key);
+        // String value
+        out.extend_from_slice(&8u32.to_le_bytes());
+        string(&mut out, value);
+    }
+    // One F32 tensor of 4 x 4
+    string(&mut out, "token_embd.weight");
+    out.extend_from_slice(&2u32.to_le_bytes());
+    out.extend_from_slice(&4u64.to_le_bytes());
+    out.extend_from_slice(&4u64.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+    std::fs::write(path, out).unwrap();
+}
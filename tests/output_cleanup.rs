@@ -0,0 +1,166 @@
+//! Model output is cleaned before it is returned as a translation.
+//!
+//! Runs the real binary in REPL mode against a fake `llama` that echoes the prompt and
+//! then prints output in the shapes llama.cpp produces for Gemma, Gemma 2, Llama 3 and
+//! Qwen 2 models: end-of-turn tokens of the wrong family, `[end of text]`, perf logs on
+//! stdout, preambles, notes, quotes and runaway repetition.
+#![cfg(unix)]
+
+mod common;
+
+use common::{write_executable, write_model};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+struct Case {
+    direction: &'static str,
+    source: &'static str,
+    /// What llama.cpp printed after the prompt
+    output: &'static str,
+    expected: &'static str,
+}
+
+const CASES: &[Case] = &[
+    // Gemma 2B with an older llama.cpp `main`, stderr merged into stdout
+    Case {
+        direction: "es-en",
+        source: "Hola, ¿cómo estás?",
+        output: "Hello, how are you? [end of text]\n\n\
+                 llama_print_timings:        load time =     733.86 ms\n\
+                 llama_print_timings:      sample time =       2.10 ms /     7 runs   (    0.30 ms per token)\n\
+                 llama_print_timings:        eval time =     601.83 ms /     6 runs   (  100.31 ms per token)\n",
+        expected: "Hello, how are you?",
+    },
+    Case {
+        direction: "es-en",
+        source: "¿Dónde está la estación de tren?",
+        output: "Where is the train station?\n\
+                 llama_perf_sampler_print:    sampling time =       1.02 ms /    12 runs   (    0.09 ms per token)\n\
+                 llama_perf_context_print:        load time =     812.40 ms\n",
+        expected: "Where is the train station?",
+    },
+    // Gemma 2 preambles and explanations
+    Case {
+        direction: "es-en",
+        source: "Buenos días a todos.",
+        output: "Here is the translation:\n\nGood morning, everyone. \n",
+        expected: "Good morning, everyone.",
+    },
+    Case {
+        direction: "es-en",
+        source: "La reunión empieza a las nueve.",
+        output: "Sure! Here's the English translation: \"The meeting starts at nine.\"\n",
+        expected: "The meeting starts at nine.",
+    },
+    Case {
+        direction: "es-en",
+        source: "El tren sale del andén 4.",
+        output: "**Translation:** The train leaves from platform 4.\n\n**Explanation:**\n\n* \"Andén\" means platform.\n* \"Sale\" means leaves.\n",
+        expected: "The train leaves from platform 4.",
+    },
+    Case {
+        direction: "es-en",
+        source: "Quisiera un café, por favor.",
+        output: "I would like a coffee, please. (Note: \"quisiera\" is a polite form of \"querer\".)\n",
+        expected: "I would like a coffee, please.",
+    },
+    Case {
+        direction: "en-es",
+        source: "The cat sleeps on the sofa.",
+        output: "Aquí está la traducción al español:\n\n\"El gato duerme en el sofá.\"\n\nNota: \"sofá\" también puede traducirse como \"sillón\".\n",
+        expected: "El gato duerme en el sofá.",
+    },
+    // Llama 3 and Qwen 2 answers, whose end tokens the Gemma template doesn't stop on
+    Case {
+        direction: "es-en",
+        source: "Hace buen tiempo hoy.",
+        output: "The weather is nice today.<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\nThe weather is nice today.",
+        expected: "The weather is nice today.",
+    },
+    Case {
+        direction: "en-es",
+        source: "See you tomorrow.",
+        output: "Hasta mañana.<|im_end|>\n<|endoftext|>Human: translate more\n",
+        expected: "Hasta mañana.",
+    },
+    // Degenerate repetition
+    Case {
+        direction: "es-en",
+        source: "Muchas gracias por tu ayuda.",
+        output: "Thank you very much for your help. Thank you very much for your help. Thank you very much for your help. Thank you very much for",
+        expected: "Thank you very much for your help.",
+    },
+    Case {
+        direction: "es-en",
+        source: "No lo sé.",
+        output: "I don't know, know, know, know, know, know, know, know",
+        expected: "I don't know",
+    },
+    // What the source has itself is kept
+    Case {
+        direction: "es-en",
+        source: "Nota: la tienda cierra a las cinco.",
+        output: "Note: the store closes at five.\n",
+        expected: "Note: the store closes at five.",
+    },
+    Case {
+        direction: "es-en",
+        source: "«Buenos días»",
+        output: "«Good morning»\n",
+        expected: "«Good morning»",
+    },
+    Case {
+        direction: "es-en",
+        source: "No, no, no, no, eso no.",
+        output: "No, no, no, no, not that.\n",
+        expected: "No, no, no, no, not that.",
+    },
+];
+
+fn translate(dir: &Path, case: &Case) -> String {
+    std::fs::write(dir.join("output.txt"), case.output).unwrap();
+    let path = format!("{}:{}", dir.join("bin").display(), std::env::var("PATH").unwrap_or_default());
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gemma-edge-translator"))
+        .current_dir(dir)
+        .env("PATH", path)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .arg("repl")
+        .arg("--gemma-model")
+        .arg(dir.join("model.gguf"))
+        .args(["--direction", case.direction])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to start the REPL");
+    writeln!(child.stdin.take().unwrap(), "{}", case.source).unwrap();
+    let output = child.wait_with_output().unwrap();
+    // The first line is the REPL banner
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.split_once('\n').map(|(_, rest)| rest.trim_end().to_string()).unwrap_or_default()
+}
+
+#[test]
+fn model_output_is_cleaned() {
+    let dir: PathBuf = std::env::temp_dir().join(format!("gemma-cleanup-test-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("bin")).unwrap();
+    // Echo the prompt as llama.cpp does, then print the captured output
+    write_executable(
+        &dir.join("bin").join("llama"),
+        "#!/bin/sh\nfor a in \"$@\"; do case \"$prev\" in -p|--prompt) P=\"$a\";; esac; prev=\"$a\"; done\n\
+         printf '%s' \"$P\"\ncat output.txt\n",
+    );
+    write_model(&dir.join("model.gguf"));
+
+    let failures: Vec<String> = CASES
+        .iter()
+        .filter_map(|case| {
+            let got = translate(&dir, case);
+            (got != case.expected).then(|| format!("{:?}: expected {:?}, got {:?}", case.source, case.expected, got))
+        })
+        .collect();
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
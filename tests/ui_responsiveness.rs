@@ -0,0 +1,113 @@
+//! `/stats` must keep answering while a slow translation is in flight.
+//!
+//! Starts the real binary with a single HTTP worker and a fake `llama` executable
+//! that takes a few seconds to "generate", then polls `/stats` mid-translation.
+#![cfg(all(feature = "ui", unix))]
+
+mod common;
+
+use common::{write_executable, write_model};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const FAKE_LLAMA_SECS: u64 = 4;
+
+struct Server {
+    child: Child,
+    dir: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn start_server(port: u16) -> Server {
+    let dir = std::env::temp_dir().join(format!("gemma-ui-test-{}", port));
+    let bin_dir = dir.join("bin");
+    std::fs::create_dir_all(&bin_dir).unwrap();
+
+    write_executable(
+        &bin_dir.join("llama"),
+        &format!("#!/bin/sh\nsleep {}\nprintf '<start_of_turn>model\\nHello\\n'\n", FAKE_LLAMA_SECS),
+    );
+    write_model(&dir.join("model.gguf"));
+    std::fs::write(dir.join("test.toml"), "[server]\nworkers = 1\n").unwrap();
+
+    let path = format!("{}:{}", bin_dir.display(), std::env::var("PATH").unwrap_or_default());
+    let child = Command::new(env!("CARGO_BIN_EXE_gemma-edge-translator"))
+        .current_dir(&dir)
+        .env("PATH", path)
+        .env("XDG_CONFIG_HOME", &dir)
+        .args(["--ui", "--bind", "127.0.0.1", "--port", &port.to_string()])
+        .arg("--config")
+        .arg(dir.join("test.toml"))
+        .arg("--gemma-model")
+        .arg(dir.join("model.gguf"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to start server");
+    let server = Server { child, dir };
+
+    let deadline = Instant::now() + Duration::from_secs(15);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline, "server did not start");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    server
+}
+
+fn http(port: u16, method: &str, path: &str, body: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn stats_stays_responsive_during_translation() {
+    let port = free_port();
+    let _server = start_server(port);
+
+    let translation = std::thread::spawn(move || {
+        http(port, "POST", "/translate", r#"{"direction":"es-en","text":"hola amigo"}"#)
+    });
+
+    // Let the translation reach llama.cpp before probing
+    std::thread::sleep(Duration::from_millis(500));
+
+    let started = Instant::now();
+    let stats = http(port, "GET", "/stats", "");
+    let elapsed = started.elapsed();
+
+    assert!(stats.starts_with("HTTP/1.1 200"), "unexpected /stats response: {}", stats);
+    assert!(
+        elapsed < Duration::from_secs(FAKE_LLAMA_SECS - 1),
+        "/stats took {:?} while a translation was running",
+        elapsed
+    );
+
+    let translated = translation.join().unwrap();
+    assert!(translated.contains("\"translated\":\"Hello\""), "unexpected /translate response: {}", translated);
+}